@@ -0,0 +1,310 @@
+use crate::organizer::actions::StalePolicy;
+use crate::organizer::conditions::parse_duration;
+use crate::organizer::conflicts::ConflictStrategy;
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Default time a file must stay unchanged before it is organised in watch mode
+pub const DEFAULT_SETTLE_SECONDS: u64 = 2;
+
+/// Exit codes of fsorg
+#[derive(Debug, Clone, Copy)]
+pub enum Exit {
+    Success = 0,
+
+    /// The command could not be carried out, e.g. an unreadable plan or journal
+    Failure = 1,
+
+    /// Unknown, missing or conflicting command line options, or an invalid rule pattern
+    Usage = 2,
+
+    /// The configurations cannot be loaded, e.g. a config file which cannot be parsed
+    Config = 3,
+
+    /// The command finished, but some files could not be handled or the rules have findings
+    Partial = 4,
+}
+
+impl Exit {
+    /// Exits the process with the code
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  The command could not be carried out, e.g. an unreadable plan or journal
+  2  Unknown, missing or conflicting command line options, or an invalid rule pattern
+  3  The configurations cannot be loaded
+  4  The command finished, but some files could not be handled or the rules have findings";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "fsorg",
+    version,
+    author,
+    about = "Organises files into directories by rules matching their names, content and metadata",
+    after_help = EXIT_CODES
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    /// Organises the source directory when no command is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Args)]
+pub struct GlobalArgs {
+    /// Config file of the user, in JSON, TOML or YAML by its extension
+    /// [default: ~/.config/fsorg/config.json]
+    #[arg(short, long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Named profile of the config, with its own rules, directories and options
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct DirectoryArgs {
+    /// Source directory containing unorganised files [default: the source of the config,
+    /// or the current working directory]
+    #[arg(short, long = "source-dir", value_name = "DIR")]
+    pub source: Option<PathBuf>,
+
+    /// Destination where to place the organised files [default: the destination of the
+    /// config, or the current working directory]
+    #[arg(short, long = "destination-dir", value_name = "DIR")]
+    pub destination: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Args)]
+/// Options overriding those of the config for scanning and organising
+pub struct OrganiseArgs {
+    #[command(flatten)]
+    pub directories: DirectoryArgs,
+
+    /// Also organises the files inside subdirectories of the source directory
+    #[arg(short = 'R', long)]
+    pub recursive: bool,
+
+    /// Limits the depth of subdirectories to descend into, implies --recursive
+    #[arg(long, value_name = "DEPTH")]
+    pub max_depth: Option<usize>,
+
+    /// Keeps the relative subdirectory structure under the rule destination
+    #[arg(long, conflicts_with = "flatten")]
+    pub keep_structure: bool,
+
+    /// Places the files directly into the rule destination
+    #[arg(long)]
+    pub flatten: bool,
+
+    /// Removes the source subdirectories which became empty after organising
+    #[arg(long)]
+    pub prune_empty: bool,
+
+    /// What to do when a destination file already exists: skip, overwrite, rename,
+    /// keep_newer, keep_larger, deduplicate [default: rename]
+    #[arg(long, value_name = "STRATEGY")]
+    pub on_conflict: Option<ConflictStrategy>,
+
+    /// Leaves alone the files modified more recently than this, e.g. 5m [default: 10s]
+    #[arg(long, value_name = "DURATION", value_parser = grace_period)]
+    pub grace: Option<String>,
+
+    /// Leaves alone the files which are currently open in any process
+    #[arg(long)]
+    pub skip_open_files: bool,
+}
+
+impl OrganiseArgs {
+    /// Whether the structure is kept, flattened, or left to the config
+    pub fn keep_structure(&self) -> Option<bool> {
+        match (self.keep_structure, self.flatten) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Args)]
+pub struct JournalArgs {
+    /// Journal file recording the executed actions
+    /// [default: ~/.local/state/fsorg/journals/journal-<timestamp>.jsonl]
+    #[arg(short, long, value_name = "FILE")]
+    pub journal: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Organises the files of the source directory (default command)
+    Organise {
+        #[command(flatten)]
+        options: OrganiseArgs,
+
+        #[command(flatten)]
+        journal: JournalArgs,
+
+        /// Organises with every profile of the config in turn, printing the combined statistics
+        #[arg(long, conflicts_with = "profile")]
+        all_profiles: bool,
+    },
+
+    /// Creates an action plan for organising the files, without touching them
+    Plan {
+        /// File to write the plan to
+        plan: String,
+
+        #[command(flatten)]
+        options: OrganiseArgs,
+
+        /// Records the content hash of every file, to detect modified files before applying the plan
+        #[arg(long)]
+        hash: bool,
+    },
+
+    /// Executes an action plan
+    Apply {
+        /// The plan to execute
+        plan: String,
+
+        /// What to do with plan entries whose file changed since planning: abort, skip, proceed
+        #[arg(long, value_name = "POLICY", default_value = "abort")]
+        on_stale: StalePolicy,
+
+        /// Destination the files must stay inside of, whatever the plan declares
+        /// [default: the destination of the config, or the current working directory]
+        #[arg(short, long = "destination-dir", value_name = "DIR")]
+        destination: Option<PathBuf>,
+
+        /// Executes a plan in the legacy text format, which records no destination root
+        #[arg(long)]
+        allow_legacy: bool,
+
+        #[command(flatten)]
+        journal: JournalArgs,
+    },
+
+    /// Reverses the actions recorded in a journal
+    Undo {
+        /// The journal of the run to undo
+        journal: PathBuf,
+    },
+
+    /// Keeps running and organises the files as they arrive in the source directory
+    Watch {
+        #[command(flatten)]
+        options: OrganiseArgs,
+
+        /// Seconds a file must stay unchanged before it is organised
+        #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_SETTLE_SECONDS)]
+        settle: u64,
+
+        #[command(flatten)]
+        journal: JournalArgs,
+    },
+
+    /// Manages and checks the rules
+    #[command(subcommand)]
+    Rules(RulesCommand),
+
+    /// Inspects, migrates and converts the config
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Organise {
+            options: OrganiseArgs::default(),
+            journal: JournalArgs::default(),
+            all_profiles: false,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RulesCommand {
+    /// Adds a rule, or changes the destination of the rule with the same pattern
+    Add {
+        /// File name regex, or glob with --glob, e.g. "(?i)^.*\.(mp3|wav)$" or "*.{mp3,wav}"
+        #[arg(allow_hyphen_values = true)]
+        pattern: String,
+
+        /// Directory for the matching files, relative to the destination directory
+        #[arg(allow_hyphen_values = true)]
+        destination: String,
+
+        /// The pattern is a glob matching the file names regardless of their case
+        #[arg(short, long)]
+        glob: bool,
+    },
+
+    /// Removes the rules with the given regex or glob
+    #[command(alias = "remove")]
+    Rm {
+        #[arg(allow_hyphen_values = true)]
+        pattern: String,
+    },
+
+    /// Lists the rules in evaluation order
+    #[command(alias = "list")]
+    Ls {
+        #[command(flatten)]
+        directories: DirectoryArgs,
+    },
+
+    /// Shows how every rule is evaluated for a file or name and where it goes
+    Test {
+        /// The file, or a name treated as a file in the source directory
+        file: PathBuf,
+
+        #[command(flatten)]
+        directories: DirectoryArgs,
+    },
+
+    /// Reports overlapping, shadowed and unmatched rules and escaping destinations,
+    /// sampling the source directory if given or configured
+    Lint {
+        #[command(flatten)]
+        directories: DirectoryArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Shows the config files of every layer, the rules along with their layer and the
+    /// settings in effect
+    Show {
+        #[command(flatten)]
+        directories: DirectoryArgs,
+    },
+
+    /// Rewrites a config file of an older version in the latest version, keeping a backup of it
+    Migrate,
+
+    /// Converts the config to the format of the given file by its extension
+    Convert {
+        /// The converted config file, e.g. ~/.config/fsorg/config.toml
+        output: PathBuf,
+    },
+
+    /// Stores the given source and destination directories in the config, or in the
+    /// selected profile
+    #[command(group(ArgGroup::new("saved").args(["source", "destination"]).required(true).multiple(true)))]
+    SaveDirs {
+        #[command(flatten)]
+        directories: DirectoryArgs,
+    },
+}
+
+/// Validates a grace period, e.g. "10s" or "5m"
+fn grace_period(duration: &str) -> Result<String, String> {
+    parse_duration(duration).map(|_| duration.to_string())
+}
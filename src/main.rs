@@ -1,109 +1,438 @@
+mod cli;
 mod organizer;
+use clap::Parser;
+use cli::{
+    Cli, Command, ConfigCommand, DirectoryArgs, Exit, GlobalArgs, OrganiseArgs, RulesCommand,
+};
 use log::error;
 use organizer::actions::Actions;
-use organizer::engine::Engine;
-use std::env;
-use std::path::PathBuf;
-
-enum UserAction<'a> {
-    AddRule(&'a str, &'a str),
-    DeleteRule(&'a str),
-    ViewRule,
-    Organise,
-    ExportPlan(&'a str),
-    ExecutePlan(&'a str),
-}
+use organizer::config_file::CONFIG_VERSION;
+use organizer::configurations::RuleError;
+use organizer::engine::{Engine, FileExplanation};
+use organizer::journal::{self, Journal};
+use organizer::lint::LintReport;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 fn main() {
     env_logger::init();
 
-    let mut engine = Engine::new();
-    let mut action = UserAction::Organise;
-
-    let mut last_argument = String::new();
-    let mut last_utilized = true;
-    let arguments: Vec<String> = env::args().skip(1).collect();
-    for argument in &arguments {
-        match argument.as_str() {
-            "-v" | "--view-rules" => action = UserAction::ViewRule,
-            "--help" | "-h" | "?" | "-?" => {
-                usage();
-                std::process::exit(0);
-            }
-            _ => {
-                if argument.starts_with("-") {
-                    last_argument = argument.clone();
-                    last_utilized = false;
-                } else {
-                    match last_argument.as_str() {
-                        "--source-dir" | "-s" => {
-                            engine.change_working_directory(PathBuf::from(argument))
-                        }
-                        "--destination-dir" | "-d" => {
-                            engine.change_destination_directory(PathBuf::from(argument))
-                        }
-                        "--config" | "-c" => engine.change_configurations(PathBuf::from(argument)),
-                        "-a" | "--add-rule" => {
-                            let rule_input: Vec<&str> = argument
-                                .splitn(2, ",")
-                                .map(|s| s.trim_matches([' ', '"']))
-                                .collect();
-                            if rule_input.len() != 2 {
-                                error!("Invalid syntax for adding a rule !");
-                                usage();
-                                std::process::exit(-1);
-                            }
-
-                            action = UserAction::AddRule(rule_input[0], rule_input[1]);
-                        }
-                        "-r" | "--remove-rule" => {
-                            action = UserAction::DeleteRule(argument.trim_matches([' ', '"']))
-                        }
-                        "-p" | "--dry-run" => action = UserAction::ExportPlan(argument),
-                        "-x" | "--execute" => action = UserAction::ExecutePlan(argument),
-                        _ => {
-                            error!("Invalid argument: {}\n", argument);
-                            usage();
-                            std::process::exit(-1);
-                        }
-                    };
-                    last_utilized = true;
-                }
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            let _ = err.print();
+            // Help and version are printed on request, anything else is a usage error
+            if err.use_stderr() {
+                Exit::Usage.exit();
             }
+            Exit::Success.exit();
         }
-    }
+    };
 
-    if !last_utilized {
-        error!("Incomplete argument provided: {}\n", arguments.join(" "));
-        usage();
-        std::process::exit(-1);
-    }
+    run(&cli.global, cli.command.unwrap_or_default()).exit();
+}
 
-    engine.load_configurations();
-    match action {
-        UserAction::Organise => {
-            let mut organising_actions = engine.generate_actions();
-            organising_actions.execute_actions();
-            print_statistics(&organising_actions);
+/// Carries out a command, returning the exit code
+fn run(global: &GlobalArgs, command: Command) -> Exit {
+    match command {
+        Command::Organise {
+            options,
+            journal,
+            all_profiles: false,
+        } => {
+            let engine = prepare_engine(global, global.profile.as_deref(), &options);
+            let mut actions = match engine.generate_actions() {
+                Ok(actions) => actions,
+                Err(err) => {
+                    error!("{} !", err);
+                    return Exit::Failure;
+                }
+            };
+            execute_with_journal(&mut actions, journal.journal);
+            engine.prune_empty_directories(&mut actions);
+            print_statistics(&actions);
+            completion(actions.get_total_files_errors())
+        }
+        Command::Organise {
+            options,
+            journal: journal_file,
+            all_profiles: true,
+        } => {
+            // clap only sees the conflict when --profile follows the command
+            if global.profile.is_some() {
+                error!("--all-profiles cannot be used with --profile !");
+                return Exit::Usage;
+            }
+            let profiles = load_engine(global, None, &options.directories).profile_names();
+            if profiles.is_empty() {
+                error!("The configurations define no profiles !");
+                return Exit::Config;
+            }
+
+            let mut totals = Actions::new();
+            let mut failed = false;
+            // All the profiles share one journal
+            let mut journal = None;
+            for profile in &profiles {
+                let engine = prepare_engine(global, Some(profile), &options);
+                println!(
+                    "Organising profile {}: {} -> {}",
+                    profile,
+                    engine.get_working_directory().display(),
+                    engine.get_destination_directory().display()
+                );
+                let mut actions = match engine.generate_actions() {
+                    Ok(actions) => actions,
+                    Err(err) => {
+                        error!("{}, skipping the profile {} !", err, profile);
+                        failed = true;
+                        continue;
+                    }
+                };
+                if !actions.is_empty() {
+                    actions.attach_journal(
+                        journal
+                            .take()
+                            .unwrap_or_else(|| create_journal(journal_file.journal.clone())),
+                    );
+                }
+                actions.execute_actions();
+                engine.prune_empty_directories(&mut actions);
+                if let Some(profile_journal) = actions.detach_journal() {
+                    journal = Some(profile_journal);
+                }
+                totals.add_statistics(&actions);
+            }
+
+            if let Some(journal) = journal {
+                totals.attach_journal(journal);
+            }
+            print_statistics(&totals);
+            if failed {
+                return Exit::Failure;
+            }
+            completion(totals.get_total_files_errors())
         }
-        UserAction::ExportPlan(file_path) => {
-            let actions = engine.generate_actions();
-            match actions.export_actions(file_path) {
-                Ok(()) => println!("Action Plan has been exported to: {}", file_path),
+        Command::Plan {
+            plan,
+            options,
+            hash,
+        } => {
+            let mut engine = prepare_engine(global, global.profile.as_deref(), &options);
+            if hash {
+                engine.enable_source_hashes();
+            }
+            let actions = match engine.generate_actions() {
+                Ok(actions) => actions,
+                Err(err) => {
+                    error!("{}, no plan has been written !", err);
+                    return Exit::Failure;
+                }
+            };
+            match actions.export_actions(&plan) {
+                Ok(()) => {
+                    println!("Action Plan has been exported to: {}", plan);
+                    completion(actions.get_total_files_errors())
+                }
                 Err(err) => {
                     error!("An occurred while exporting the plan: {}", err);
-                    std::process::exit(-1);
+                    Exit::Failure
                 }
             }
         }
-        UserAction::ExecutePlan(plan_path) => {
-            let mut actions = Actions::from(plan_path);
-            actions.execute_actions();
+        Command::Apply {
+            plan,
+            on_stale,
+            destination,
+            allow_legacy,
+            journal,
+        } => {
+            let mut actions = match Actions::from(&plan) {
+                Ok(actions) => actions,
+                Err(err) => {
+                    error!(
+                        "Unable to read the plan {}: {}\nCannot proceed further with this plan !",
+                        plan, err
+                    );
+                    return Exit::Failure;
+                }
+            };
+            if actions.is_legacy() && !allow_legacy {
+                error!(
+                    "The plan {} is in the legacy text format, which records no destination root, pass --allow-legacy to execute it anyway !",
+                    plan
+                );
+                return Exit::Failure;
+            }
+            // The files stay inside of the current destination, not the one the plan declares
+            let directories = DirectoryArgs {
+                source: None,
+                destination,
+            };
+            load_engine(global, global.profile.as_deref(), &directories).confine_plan(&mut actions);
+            if !actions.validate_actions(on_stale) {
+                print_statistics(&actions);
+                return Exit::Failure;
+            }
+            execute_with_journal(&mut actions, journal.journal);
             print_statistics(&actions);
+            completion(actions.get_total_files_errors())
+        }
+        Command::Undo { journal } => match journal::undo(&journal) {
+            Ok(report) => {
+                println!("***");
+                println!("Total files restored: {}", report.total_files_restored);
+                println!("Total files missing: {}", report.total_files_missing);
+                println!("Total files changed: {}", report.total_files_changed);
+                println!("Total errors encountered: {}", report.total_files_errors);
+                completion(report.total_files_errors)
+            }
+            Err(err) => {
+                error!("Unable to read the journal {}: {}", journal.display(), err);
+                Exit::Failure
+            }
+        },
+        Command::Watch {
+            options,
+            settle,
+            journal: journal_file,
+        } => {
+            let engine = prepare_engine(global, global.profile.as_deref(), &options);
+            // All the batches of a watch session share one journal
+            let mut journal = None;
+            let result = engine.watch(Duration::from_secs(settle), |actions| {
+                let session_journal = journal
+                    .take()
+                    .unwrap_or_else(|| create_journal(journal_file.journal.clone()));
+                actions.attach_journal(session_journal);
+                actions.execute_actions();
+                journal = actions.detach_journal();
+            });
+            match result {
+                Ok(()) => Exit::Success,
+                Err(err) => {
+                    error!(
+                        "Unable to watch the working directory {}: {}",
+                        engine.get_working_directory().display(),
+                        err
+                    );
+                    Exit::Failure
+                }
+            }
+        }
+        Command::Rules(command) => run_rules(global, command),
+        Command::Config(command) => run_config(global, command),
+    }
+}
+
+/// Carries out a command of `fsorg rules`, returning the exit code
+fn run_rules(global: &GlobalArgs, command: RulesCommand) -> Exit {
+    let profile = global.profile.as_deref();
+    match command {
+        RulesCommand::Add {
+            pattern,
+            destination,
+            glob,
+        } => {
+            let mut engine = load_engine(global, profile, &DirectoryArgs::default());
+            let result = if glob {
+                engine.add_glob_rule(&pattern, &destination)
+            } else {
+                engine.add_rule(&pattern, &destination)
+            };
+            rule_edited(result)
+        }
+        RulesCommand::Rm { pattern } => rule_edited(
+            load_engine(global, profile, &DirectoryArgs::default()).delete_rule(&pattern),
+        ),
+        RulesCommand::Ls { directories } => {
+            only_print_rules(&load_engine(global, profile, &directories));
+            Exit::Success
+        }
+        RulesCommand::Test { file, directories } => {
+            match load_engine(global, profile, &directories).explain(&file) {
+                Ok(explanation) => {
+                    print_explanation(&explanation);
+                    Exit::Success
+                }
+                Err(err) => {
+                    error!("{} !", err);
+                    Exit::Failure
+                }
+            }
+        }
+        RulesCommand::Lint { directories } => {
+            let engine = load_engine(global, profile, &directories);
+            match engine.lint_rules(engine.get_source_directory().map(PathBuf::as_path)) {
+                Ok(report) => {
+                    print_lint_report(&report, engine.get_working_directory());
+                    if report.has_findings() {
+                        Exit::Partial
+                    } else {
+                        Exit::Success
+                    }
+                }
+                Err(err) => {
+                    error!("{} !", err);
+                    Exit::Failure
+                }
+            }
+        }
+    }
+}
+
+/// The exit code of adding or deleting a rule
+fn rule_edited(result: Result<(), RuleError>) -> Exit {
+    match result {
+        Ok(()) => Exit::Success,
+        Err(err) => {
+            error!("{} !", err);
+            match err {
+                RuleError::Invalid(_) => Exit::Usage,
+                RuleError::Failed(_) => Exit::Failure,
+            }
+        }
+    }
+}
+
+/// Carries out a command of `fsorg config`, returning the exit code
+fn run_config(global: &GlobalArgs, command: ConfigCommand) -> Exit {
+    let profile = global.profile.as_deref();
+    let result = match command {
+        ConfigCommand::Show { directories } => {
+            print_effective_configurations(&load_engine(global, profile, &directories));
+            Ok(())
+        }
+        ConfigCommand::Migrate => load_engine(global, profile, &DirectoryArgs::default())
+            .migrate_configurations()
+            .map(|migrated| match migrated {
+                Some(version) => println!(
+                    "Migrated the configurations from version {} to {}, the original file is kept as a backup",
+                    version, CONFIG_VERSION
+                ),
+                None => println!(
+                    "The configurations are already at version {}",
+                    CONFIG_VERSION
+                ),
+            }),
+        ConfigCommand::Convert { output } => {
+            load_engine(global, profile, &DirectoryArgs::default())
+                .convert_configurations(&output)
+                .map(|()| {
+                    println!(
+                        "The configurations have been converted to: {}",
+                        output.display()
+                    )
+                })
+        }
+        ConfigCommand::SaveDirs { directories } => load_engine(global, profile, &directories)
+            .save_directories()
+            .map(|saved| {
+                for (key, directory) in saved {
+                    println!("Saved the {} directory: {}", key, directory);
+                }
+            }),
+    };
+
+    match result {
+        Ok(()) => Exit::Success,
+        Err(err) => {
+            error!("{} !", err);
+            Exit::Failure
+        }
+    }
+}
+
+/// Loads the configurations of a profile, the given directories take precedence over
+/// those of the config. Exits if the configurations cannot be loaded.
+fn load_engine(global: &GlobalArgs, profile: Option<&str>, directories: &DirectoryArgs) -> Engine {
+    let mut engine = Engine::new();
+    if let Some(directory) = &directories.source {
+        engine.change_working_directory(directory.clone());
+    }
+    if let Some(directory) = &directories.destination {
+        engine.change_destination_directory(directory.clone());
+    }
+    if let Some(config_file) = &global.config {
+        engine.change_configurations(config_file.clone());
+    }
+    if let Some(profile) = profile {
+        engine.select_profile(profile);
+    }
+
+    if let Err(err) = engine.load_configurations() {
+        error!("{} !", err);
+        Exit::Config.exit();
+    }
+    engine
+}
+
+/// Loads the configurations of a profile, command line options take precedence over them
+fn prepare_engine(global: &GlobalArgs, profile: Option<&str>, options: &OrganiseArgs) -> Engine {
+    let mut engine = load_engine(global, profile, &options.directories);
+
+    if let Some(strategy) = options.on_conflict {
+        engine.change_conflict_strategy(strategy);
+    }
+    let scan_options = engine.scan_options_mut();
+    if options.recursive || options.max_depth.is_some() {
+        scan_options.recursive = true;
+    }
+    if options.max_depth.is_some() {
+        scan_options.max_depth = options.max_depth;
+    }
+    if let Some(keep_structure) = options.keep_structure() {
+        scan_options.keep_structure = keep_structure;
+    }
+    if options.prune_empty {
+        scan_options.prune_empty_directories = true;
+    }
+    let safeguard_options = engine.safeguard_options_mut();
+    if let Some(grace_period) = &options.grace {
+        safeguard_options.grace_period = grace_period.clone();
+    }
+    if options.skip_open_files {
+        safeguard_options.skip_open_files = true;
+    }
+    engine
+}
+
+/// Success, unless some files could not be handled
+fn completion(errors: u32) -> Exit {
+    if errors > 0 {
+        Exit::Partial
+    } else {
+        Exit::Success
+    }
+}
+
+/// Executes the actions while recording them into a journal, so that they can be undone later
+fn execute_with_journal(actions: &mut Actions, journal_file: Option<PathBuf>) {
+    if !actions.is_empty() {
+        actions.attach_journal(create_journal(journal_file));
+    }
+
+    actions.execute_actions();
+}
+
+/// Creates the journal at the given or the default location, exits if that is not possible
+fn create_journal(journal_file: Option<PathBuf>) -> Journal {
+    let result = match &journal_file {
+        Some(journal_file) => Journal::create(journal_file.clone()),
+        None => Journal::create_default(),
+    };
+    match result {
+        Ok(journal) => journal,
+        Err(err) => {
+            error!(
+                "Unable to create the journal {}: {}\nCannot proceed without a journal !",
+                journal_file
+                    .unwrap_or_else(journal::default_journal_file)
+                    .display(),
+                err
+            );
+            Exit::Failure.exit();
         }
-        UserAction::ViewRule => only_print_rules(&engine),
-        UserAction::AddRule(pattern, destination) => engine.add_rule(pattern, destination),
-        UserAction::DeleteRule(pattern) => engine.delete_rule(pattern),
     }
 }
 
@@ -111,79 +440,262 @@ fn print_statistics(actions: &Actions) {
     println!("***");
     println!("Total files scanned: {}", actions.get_total_files_scanned());
     println!("Total files moved: {}", actions.get_total_files_moved());
+    println!("Total files copied: {}", actions.get_total_files_copied());
+    println!("Total files linked: {}", actions.get_total_files_linked());
+    println!("Total files trashed: {}", actions.get_total_files_trashed());
+    println!("Total files deleted: {}", actions.get_total_files_deleted());
     println!("Total files skipped: {}", actions.get_total_files_skipped());
     println!(
-        "Total errors encountered: {}",
-        actions.get_total_files_errors()
-    );
-}
-
-fn usage() {
-    println!("Authors: {}", env!("CARGO_PKG_AUTHORS"));
-    println!("Version: {}", env!("CARGO_PKG_VERSION"));
-    let left_width = 30;
-    println!("Syntax: {:>10}", "fsorg [OPTIONS]");
-    println!(
-        "{:>left_width$} Specify the json config file. By default the config is present at ~/.fsorg.json",
-        "--config | -c"
-    );
-    println!(
-        "{:>left_width$} Source directory containing unorganised files. (By default it is current working directory).",
-        "--source-dir | -s"
-    );
-    println!(
-        "{:>left_width$} Destination where to place the organized files. (By default it is current working directory).",
-        "--destination-dir | -d"
+        "Total files deferred: {}",
+        actions.get_total_files_deferred()
     );
+    println!("Total files ignored: {}", actions.get_total_files_ignored());
+    println!("Total files renamed: {}", actions.get_total_files_renamed());
     println!(
-        "{:>left_width$} Adds a rule: fsorg -a \"(?i)^.*\\.(mp3|wav|ogg|flac)$\", \"Videos\"",
-        "--add-rule | -a"
+        "Total files overwritten: {}",
+        actions.get_total_files_overwritten()
     );
     println!(
-        "{:>left_width$} Removes a rule: fsorg -r \"(?i)^.*\\.(mp3|wav|ogg|flac)$\"",
-        "--remove-rule | -r"
+        "Total duplicates removed: {}",
+        actions.get_total_duplicates_removed()
     );
     println!(
-        "{:>left_width$} Views the current rules present in specified or default configs.",
-        "--view-rules | -v"
+        "Total conflicts skipped: {}",
+        actions.get_total_conflicts_skipped()
     );
+    println!("Total stale entries: {}", actions.get_total_files_stale());
     println!(
-        "{:>left_width$} Creates an action plan for organising the files: fsorg [OTHER OPTIONS] -p plan1.txt",
-        "--dry-run | -p"
+        "Total errors encountered: {}",
+        actions.get_total_files_errors()
     );
     println!(
-        "{:>left_width$} Executes the provided plan: fsorg -x plan1.txt",
-        "--execute | -x"
+        "Total directories pruned: {}",
+        actions.get_total_directories_pruned()
     );
+    if let Some(journal_path) = actions.get_journal_path() {
+        println!(
+            "Journal written to: {} (undo with: fsorg undo {})",
+            journal_path.display(),
+            journal_path.display()
+        );
+    }
+}
+
+/// Prints how every rule was evaluated for a file and what organising would do with it
+fn print_explanation(explanation: &FileExplanation) {
+    let rules = &explanation.rules;
+    println!("File: {}", explanation.file.display());
+    println!("Relative path: {}", explanation.relative_path.display());
+    if let Some(mime_type) = rules.mime_type {
+        println!("Detected MIME type: {}", mime_type);
+    }
+    println!("***");
+
+    let outcome = |matched: bool| if matched { "matched" } else { "not matched" };
+    for (index, evaluation) in rules.evaluations.iter().enumerate() {
+        let verdict = if rules.winner == Some(index) {
+            "winner"
+        } else if evaluation.matched() {
+            "matched"
+        } else {
+            "no match"
+        };
+        println!("{:>3}. [{}] {}", index + 1, verdict, evaluation.description);
+
+        let mut checks = vec![format!("name {}", outcome(evaluation.name_matched))];
+        if let Some(mime_matched) = evaluation.mime_matched {
+            checks.push(format!("mime {}", outcome(mime_matched)));
+        }
+        for (condition, held) in &evaluation.conditions {
+            checks.push(format!(
+                "{} {}",
+                condition,
+                if *held { "held" } else { "failed" }
+            ));
+        }
+        println!("     {}", checks.join(", "));
+    }
+    println!("***");
+
+    match (&rules.rule_match, &explanation.destination) {
+        (Some(rule_match), Some(destination)) if rule_match.action.has_destination() => println!(
+            "Outcome: {} to {}",
+            rule_match.action,
+            destination.display()
+        ),
+        (Some(rule_match), _) => println!("Outcome: {}", rule_match.action),
+        (None, _) => println!("Outcome: no rule matches, the file is skipped"),
+    }
+    if explanation.ignored {
+        println!("Note: the file is ignored, so it is left alone");
+    }
+    if let Some(deferral) = explanation.deferral {
+        println!("Note: the file is deferred for now as {}", deferral);
+    }
+}
+
+/// Prints the findings of the rule analysis, rules are numbered in evaluation order
+fn print_lint_report(report: &LintReport, source_directory: &Path) {
+    if report.real_files {
+        println!(
+            "Sampled {} files of {}",
+            report.samples,
+            source_directory.display()
+        );
+    } else {
+        println!(
+            "Sampled {} file names of the built-in corpus, rules with conditions may not match them",
+            report.samples
+        );
+    }
+    for (index, rule) in report.rules.iter().enumerate() {
+        if report.unjudged.contains(&index) {
+            println!(
+                "{:>3}. {} (checks the content, not judged)",
+                index + 1,
+                rule
+            );
+        } else {
+            println!("{:>3}. {}", index + 1, rule);
+        }
+    }
+    println!("***");
+
+    if !report.overlaps.is_empty() {
+        println!("Overlapping rules:");
+        for overlap in &report.overlaps {
+            let outcome = if overlap.taken_by_earlier {
+                format!("only #{} takes them", overlap.earlier + 1)
+            } else {
+                format!(
+                    "#{} takes them when its conditions hold",
+                    overlap.earlier + 1
+                )
+            };
+            println!(
+                "  #{} and #{} both match {} of the samples, e.g. {}, {}",
+                overlap.earlier + 1,
+                overlap.later + 1,
+                overlap.count,
+                overlap.example,
+                outcome
+            );
+        }
+    }
+    if !report.shadowed.is_empty() {
+        println!("Shadowed rules:");
+        for shadowing in &report.shadowed {
+            let shadowed_by: Vec<String> = shadowing
+                .shadowed_by
+                .iter()
+                .map(|rule| format!("#{}", rule + 1))
+                .collect();
+            println!(
+                "  #{} never fires, all of its files are taken by {}",
+                shadowing.rule + 1,
+                shadowed_by.join(", ")
+            );
+        }
+    }
+    if !report.unmatched.is_empty() {
+        println!("Rules matching nothing:");
+        for rule in &report.unmatched {
+            println!("  #{} {}", rule + 1, report.rules[*rule]);
+        }
+    }
+    if !report.escaping.is_empty() {
+        println!("Destinations escaping the destination root:");
+        for (rule, reason) in &report.escaping {
+            println!("  {}: {}", rule, reason);
+        }
+    }
+    if !report.has_findings() {
+        println!("No findings");
+    }
+}
+
+/// Prints the configurations file of every layer, the rules in evaluation order along
+/// with the layer they come from, and the settings in effect
+fn print_effective_configurations(engine: &Engine) {
+    let (layers, settings) = engine.effective_configurations();
+    println!("Configuration layers, in increasing precedence:");
+    for (layer, path, present) in layers {
+        println!(
+            "  {:<10} {} ({})",
+            layer.to_string(),
+            path.display(),
+            if *present { "loaded" } else { "not present" }
+        );
+    }
+
+    if let Some(profile) = engine.get_profile() {
+        println!("\nProfile: {}", profile);
+    }
+
+    println!("\nRules, in evaluation order:");
+    let rules = engine.retrieve_rules();
+    if rules.is_empty() {
+        println!("  none");
+    }
+    for (index, rule) in rules.iter().enumerate() {
+        println!(
+            "  {}. [{}] {} -> {} (priority {})",
+            index + 1,
+            rule.layer,
+            rule.describe(),
+            rule.describe_action(),
+            rule.get_priority()
+        );
+    }
+
+    println!("\nSettings:");
+    match serde_json::to_string_pretty(&settings) {
+        Ok(settings) => println!("{}", settings),
+        Err(err) => error!("Unable to serialize the settings: {}", err),
+    }
 }
 
 fn only_print_rules(engine: &Engine) {
+    let rules = engine.retrieve_rules();
     let mut width_pat = 20;
     let mut width_dest = 20;
-    for (pat, dest) in engine.retrieve_rules() {
-        width_pat = if width_pat < pat.len() {
-            pat.len()
+    let width_prio = 8;
+    for rule in &rules {
+        width_pat = if width_pat < rule.describe().len() {
+            rule.describe().len()
         } else {
             width_pat
         };
-        width_dest = if width_dest < dest.len() {
-            dest.len()
+        width_dest = if width_dest < rule.describe_action().len() {
+            rule.describe_action().len()
         } else {
             width_dest
         };
     }
 
-    println!("+{:-<width_pat$} + {:->width_dest$}+", "", "");
     println!(
-        "|{:<width_pat$} | {:>width_dest$}|",
-        "Regex", "Destinations"
+        "+{:-<width_pat$} + {:->width_dest$} + {:->width_prio$}+",
+        "", "", ""
     );
-    for (pattern, destination) in engine.retrieve_rules() {
-        println!("+{:-<width_pat$} + {:->width_dest$}+", "", "");
-        println!("|{:<width_pat$} | {:>width_dest$}|", pattern, destination);
+    println!(
+        "|{:<width_pat$} | {:>width_dest$} | {:>width_prio$}|",
+        "Pattern", "Destinations", "Priority"
+    );
+    for rule in &rules {
+        println!(
+            "+{:-<width_pat$} + {:->width_dest$} + {:->width_prio$}+",
+            "", "", ""
+        );
+        println!(
+            "|{:<width_pat$} | {:>width_dest$} | {:>width_prio$}|",
+            rule.describe(),
+            rule.describe_action(),
+            rule.get_priority()
+        );
     }
 
-    println!("+{:-<width_pat$} + {:->width_dest$}+", "", "");
-
-    std::process::exit(0);
+    println!(
+        "+{:-<width_pat$} + {:->width_dest$} + {:->width_prio$}+",
+        "", "", ""
+    );
 }
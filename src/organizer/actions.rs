@@ -1,109 +1,640 @@
-use super::move_file_safely;
+use super::conflicts::{self, ConflictStrategy, Decision};
+use super::journal::{Journal, JournalEntry, Outcome};
+use super::plan::{self, PlanHeader};
+use super::trash;
+use super::{hash_file, modified_nanos, move_file_safely, normalize_path};
 use log::error;
+use serde::{Deserialize, Serialize};
 use std::{
-    fs::{self, File},
-    io::{BufRead, BufReader, Result, Write},
-    path::PathBuf,
+    collections::{BTreeSet, HashMap},
+    fmt, fs,
+    io::Result,
+    path::{self, Path, PathBuf},
+    str::FromStr,
 };
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Kind of file operation
+pub enum ActionKind {
+    /// Move the file to the destination
+    #[default]
+    Move,
+    /// Copy the file to the destination, leaving the source in place
+    Copy,
+    /// Create a symbolic link to the file at the destination
+    Symlink,
+    /// Create a hard link to the file at the destination
+    Hardlink,
+    /// Move the file into the trash, the destination is ignored
+    Trash,
+    /// Delete the file, the destination is ignored
+    Delete,
+}
+
+impl ActionKind {
+    /// Whether the action places the file at a destination, and thus may conflict
+    pub fn has_destination(&self) -> bool {
+        !matches!(self, ActionKind::Trash | ActionKind::Delete)
+    }
+
+    /// Whether the source file is gone after the action
+    pub fn removes_source(&self) -> bool {
+        matches!(
+            self,
+            ActionKind::Move | ActionKind::Trash | ActionKind::Delete
+        )
+    }
+
+    fn past_tense(&self) -> &'static str {
+        match self {
+            ActionKind::Move => "Moved",
+            ActionKind::Copy => "Copied",
+            ActionKind::Symlink => "Symlinked",
+            ActionKind::Hardlink => "Hardlinked",
+            ActionKind::Trash => "Trashed",
+            ActionKind::Delete => "Deleted",
+        }
+    }
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            ActionKind::Move => "move",
+            ActionKind::Copy => "copy",
+            ActionKind::Symlink => "symlink",
+            ActionKind::Hardlink => "hardlink",
+            ActionKind::Trash => "trash",
+            ActionKind::Delete => "delete",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// What to do with actions whose source file changed since planning
+pub enum StalePolicy {
+    /// Do not execute any action
+    #[default]
+    Abort,
+    /// Execute only the actions whose source file is unchanged
+    Skip,
+    /// Execute all the actions anyway
+    Proceed,
+}
+
+impl FromStr for StalePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "abort" => Ok(StalePolicy::Abort),
+            "skip" => Ok(StalePolicy::Skip),
+            "proceed" => Ok(StalePolicy::Proceed),
+            _ => Err(format!("Unknown stale plan policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A single planned file operation
+pub struct Action {
+    #[serde(default)]
+    pub kind: ActionKind,
+
+    pub source: PathBuf,
+
+    /// Empty for the actions without a destination
+    #[serde(default, skip_serializing_if = "path_is_empty")]
+    pub destination: PathBuf,
+
+    /// Strategy to apply if the destination is taken
+    #[serde(default)]
+    pub on_conflict: ConflictStrategy,
+
+    /// Decision taken for the destination while planning
+    #[serde(default)]
+    pub decision: Decision,
+
+    /// Size of the source file while planning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+
+    /// Modification time of the source file while planning, nanoseconds since the unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+
+    /// Content hash of the source file while planning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+
+    /// The rule allowed the destination to lie outside of the destination root
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub allow_absolute: bool,
+
+    /// Set when the source file changed since planning and the action must not be executed
+    #[serde(skip)]
+    pub stale: bool,
+}
+
+fn path_is_empty(path: &Path) -> bool {
+    path.as_os_str().is_empty()
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl Action {
+    /// Tells why the source file no longer matches the state recorded while planning
+    fn staleness(&self) -> Option<String> {
+        let metadata = match fs::metadata(&self.source) {
+            Ok(metadata) => metadata,
+            Err(err) => return Some(format!("cannot be accessed: {}", err)),
+        };
+
+        if self.size.is_some_and(|size| size != metadata.len()) {
+            return Some("size changed".to_string());
+        }
+        if self.modified.is_some() && self.modified != modified_nanos(&metadata) {
+            return Some("modification time changed".to_string());
+        }
+        if let Some(hash) = &self.hash {
+            match hash_file(&self.source) {
+                Ok(current) if &current == hash => {}
+                Ok(_) => return Some("content changed".to_string()),
+                Err(err) => return Some(format!("cannot be hashed: {}", err)),
+            }
+        }
+
+        None
+    }
+
+    /// Whether the destination lies outside of the destination root, once normalised or
+    /// once its existing parent directories are resolved, as a symbolic link inside of the
+    /// root may lead out of it. Without a root nothing can be checked, so every
+    /// destination escapes.
+    fn escapes(&self, destination_root: &Path) -> bool {
+        if !self.kind.has_destination() || self.allow_absolute {
+            return false;
+        }
+        if path_is_empty(destination_root) {
+            return true;
+        }
+
+        let destination =
+            normalize_path(&path::absolute(&self.destination).unwrap_or(self.destination.clone()));
+        let root = normalize_path(
+            &path::absolute(destination_root).unwrap_or(destination_root.to_path_buf()),
+        );
+        if !destination.starts_with(&root) {
+            return true;
+        }
+
+        // The closest existing parent lies inside of the root, unless the root is missing
+        match fs::canonicalize(&root) {
+            Ok(root) => destination
+                .ancestors()
+                .skip(1)
+                .find_map(|parent| fs::canonicalize(parent).ok())
+                .is_some_and(|parent| !parent.starts_with(root)),
+            Err(_) => false,
+        }
+    }
+}
+
 pub struct Actions {
-    /// actions (file, destination)
-    actions: Vec<(PathBuf, PathBuf)>,
+    /// Roots and configurations the actions are generated for
+    header: PlanHeader,
+
+    /// Planned actions
+    actions: Vec<Action>,
+
+    /// Destinations claimed by the planned actions -> index of the action
+    claimed: HashMap<PathBuf, usize>,
+
+    /// Journal of the executed actions
+    journal: Option<Journal>,
+
+    /// Record the content hash of the source files while planning
+    hash_sources: bool,
 
     /// Statistics
     pub total_files_scanned: u32,
     total_files_moved: u32,
+    total_files_copied: u32,
+    total_files_linked: u32,
+    total_files_trashed: u32,
+    total_files_deleted: u32,
     pub total_files_skipped: u32,
+    pub total_files_deferred: u32,
+    pub total_files_ignored: u32,
     pub total_files_errors: u32,
+    total_files_renamed: u32,
+    total_files_overwritten: u32,
+    total_duplicates_removed: u32,
+    total_conflicts_skipped: u32,
+    total_files_stale: u32,
+    total_directories_pruned: u32,
 }
 
 impl Actions {
     pub fn new() -> Self {
         Self {
+            header: PlanHeader::default(),
             actions: vec![],
+            claimed: HashMap::new(),
+            journal: None,
+            hash_sources: false,
             total_files_scanned: 0,
             total_files_moved: 0,
+            total_files_copied: 0,
+            total_files_linked: 0,
+            total_files_trashed: 0,
+            total_files_deleted: 0,
             total_files_skipped: 0,
+            total_files_deferred: 0,
+            total_files_ignored: 0,
             total_files_errors: 0,
+            total_files_renamed: 0,
+            total_files_overwritten: 0,
+            total_duplicates_removed: 0,
+            total_conflicts_skipped: 0,
+            total_files_stale: 0,
+            total_directories_pruned: 0,
         }
     }
 
-    pub fn from(file_name: &str) -> Self {
+    /// Reads a plan file, see [`plan::read_plan`]
+    pub fn from(file_name: &str) -> Result<Self> {
+        let (header, plan) = plan::read_plan(file_name)?;
+
         let mut actions = Actions::new();
+        actions.header = header;
+        for action in plan {
+            actions.add_action(action);
+        }
+
+        Ok(actions)
+    }
+
+    /// Records the roots and configurations the actions are generated for
+    pub fn set_header(&mut self, header: PlanHeader) {
+        self.header = header;
+    }
 
-        match File::open(file_name) {
-            Ok(file) => {
-                for line in BufReader::new(file).lines().map_while(Result::ok) {
-                    let line: Vec<&str> = line
-                        .splitn(2, "->")
-                        .map(|s| s.trim_matches([' ', '"']))
-                        .collect();
-                    actions.add_action(PathBuf::from(line[0]), PathBuf::from(line[1]));
+    /// Whether the actions were read from a plan in the legacy text format, which
+    /// records no roots
+    pub fn is_legacy(&self) -> bool {
+        path_is_empty(&self.header.destination_root)
+    }
+
+    /// Confines the actions read from a plan to the given destination root rather than
+    /// the one the plan declares. An action may only leave the root when `allows_absolute`
+    /// holds for its source file and its path relative to the source root of the plan,
+    /// whatever the plan claims.
+    pub fn confine(
+        &mut self,
+        destination_root: PathBuf,
+        allows_absolute: impl Fn(&Path, &Path) -> bool,
+    ) {
+        for action in &mut self.actions {
+            if !action.allow_absolute {
+                continue;
+            }
+            let relative = match action.source.strip_prefix(&self.header.source_root) {
+                Ok(relative) if !path_is_empty(&self.header.source_root) => relative,
+                _ => action
+                    .source
+                    .file_name()
+                    .map(Path::new)
+                    .unwrap_or(&action.source),
+            };
+            action.allow_absolute = allows_absolute(&action.source, relative);
+        }
+        self.header.destination_root = destination_root;
+    }
+
+    /// Registers an action
+    fn add_action(&mut self, action: Action) {
+        if action.kind.has_destination()
+            && !matches!(action.decision, Decision::Skip | Decision::DropDuplicate)
+        {
+            self.claimed
+                .insert(action.destination.clone(), self.actions.len());
+        }
+        self.actions.push(action);
+    }
+
+    /// Plans applying the action kind to the source file, a taken destination (either by an
+    /// existing file or by an already planned action) is resolved using the conflict strategy.
+    /// The destination is ignored for the kinds without a destination.
+    pub fn plan_action(
+        &mut self,
+        kind: ActionKind,
+        source: PathBuf,
+        destination: PathBuf,
+        on_conflict: ConflictStrategy,
+        allow_absolute: bool,
+    ) {
+        let (decision, destination) = if kind.has_destination() {
+            let destination = normalize_path(&path::absolute(&destination).unwrap_or(destination));
+            let existing = if destination.symlink_metadata().is_ok() {
+                // The file is already in place
+                if fs::canonicalize(&destination).is_ok_and(|d| d == source) {
+                    self.total_files_skipped += 1;
+                    return;
                 }
+                Some(destination.clone())
+            } else {
+                self.claimed
+                    .get(&destination)
+                    .map(|&index| self.actions[index].source.clone())
+            };
+
+            match existing {
+                Some(existing) => {
+                    conflicts::resolve(&source, &destination, &existing, on_conflict, |candidate| {
+                        candidate.symlink_metadata().is_ok() || self.claimed.contains_key(candidate)
+                    })
+                }
+                None => (Decision::NoConflict, destination),
             }
-            Err(err) => {
-                error!(
-                    "Fatal error occurred while opening plan {}: {}\nCannot proceed further with this plan !",
-                    file_name, err
-                );
+        } else {
+            (Decision::NoConflict, PathBuf::new())
+        };
+
+        // Fingerprint of the source, for detecting changes before executing the plan
+        let (size, modified) = match fs::metadata(&source) {
+            Ok(metadata) => (Some(metadata.len()), modified_nanos(&metadata)),
+            Err(_) => (None, None),
+        };
+        let hash = if self.hash_sources {
+            hash_file(&source)
+                .inspect_err(|err| error!("Unable to hash file {}: {}", source.display(), err))
+                .ok()
+        } else {
+            None
+        };
+
+        self.add_action(Action {
+            kind,
+            source,
+            destination,
+            on_conflict,
+            decision,
+            size,
+            modified,
+            hash,
+            allow_absolute,
+            stale: false,
+        });
+    }
+
+    /// Records the content hash of the source files in the planned actions
+    pub fn set_hash_sources(&mut self, hash_sources: bool) {
+        self.hash_sources = hash_sources;
+    }
+
+    /// Checks every action for source files which were modified, renamed or deleted
+    /// since planning and applies the policy to them.
+    ///
+    /// Returns false if the actions must not be executed.
+    pub fn validate_actions(&mut self, policy: StalePolicy) -> bool {
+        for action in &mut self.actions {
+            if let Some(reason) = action.staleness() {
+                // increment stale files counter
+                self.total_files_stale += 1;
+                action.stale = policy == StalePolicy::Skip;
+                println!("Stale plan entry {}: {}", action.source.display(), reason);
             }
         }
 
-        actions
-    }
+        if self.total_files_stale > 0 && policy == StalePolicy::Abort {
+            error!(
+                "{} plan entries are stale, regenerate the plan or choose another stale policy !",
+                self.total_files_stale
+            );
+            return false;
+        }
 
-    /// Registers an action
-    pub fn add_action(&mut self, source: PathBuf, destination: PathBuf) {
-        self.actions.push((source, destination));
+        true
     }
 
-    /// Executes the actions
+    /// Executes the actions, the conflict strategy is applied again since the
+    /// destinations might have changed after planning.
     pub fn execute_actions(&mut self) {
-        for (source_path, destination) in &self.actions {
-            match move_file_safely(source_path.as_path(), destination.as_path()) {
-                Ok(()) => {
-                    // increment moved files counter
-                    self.total_files_moved += 1;
+        for action in &self.actions {
+            if action.stale {
+                continue;
+            }
+            if action.escapes(&self.header.destination_root) {
+                // increment error counter
+                self.total_files_errors += 1;
+                error!(
+                    "Refusing to place file {} at {} outside of the destination root {} !",
+                    action.source.display(),
+                    action.destination.display(),
+                    self.header.destination_root.display()
+                );
+                continue;
+            }
+
+            let source_path = &action.source;
+            let (decision, destination) = if !action.kind.has_destination() {
+                (Decision::NoConflict, PathBuf::new())
+            } else if action.destination.symlink_metadata().is_ok() {
+                conflicts::resolve(
+                    source_path,
+                    &action.destination,
+                    &action.destination,
+                    action.on_conflict,
+                    |candidate| candidate.symlink_metadata().is_ok(),
+                )
+            } else if action.decision == Decision::Rename {
+                (Decision::Rename, action.destination.clone())
+            } else {
+                (Decision::NoConflict, action.destination.clone())
+            };
+
+            let entry = match decision {
+                Decision::Skip => {
+                    // increment skipped conflicts counter
+                    self.total_conflicts_skipped += 1;
                     println!(
-                        "Moved file {} to {}",
+                        "Skipped file {} as {} already exists",
                         source_path.display(),
                         destination.display()
                     );
+                    JournalEntry::new(action.kind, source_path, &destination, Outcome::Skipped)
                 }
-                Err(err) => {
-                    // increment error counter
-                    self.total_files_errors += 1;
-                    error!(
-                        "An error occurred while moving file {} to {}: {}",
+                // Copying or linking a duplicate would not change anything
+                Decision::DropDuplicate if !action.kind.removes_source() => {
+                    // increment skipped conflicts counter
+                    self.total_conflicts_skipped += 1;
+                    println!(
+                        "Skipped file {} as {} is a duplicate of it",
                         source_path.display(),
-                        destination.display(),
-                        err
+                        destination.display()
                     );
+                    JournalEntry::new(action.kind, source_path, &destination, Outcome::Skipped)
+                }
+                Decision::DropDuplicate => match fs::remove_file(source_path) {
+                    Ok(()) => {
+                        // increment removed duplicates counter
+                        self.total_duplicates_removed += 1;
+                        println!(
+                            "Removed file {} as a duplicate of {}",
+                            source_path.display(),
+                            destination.display()
+                        );
+                        JournalEntry::new(
+                            action.kind,
+                            source_path,
+                            &destination,
+                            Outcome::DuplicateRemoved,
+                        )
+                    }
+                    Err(err) => {
+                        // increment error counter
+                        self.total_files_errors += 1;
+                        error!(
+                            "An error occurred while removing duplicate file {}: {}",
+                            source_path.display(),
+                            err
+                        );
+                        JournalEntry::failed(action.kind, source_path, &destination, &err)
+                    }
+                },
+                Decision::NoConflict | Decision::Overwrite | Decision::Rename => {
+                    let result = apply_action(
+                        action.kind,
+                        source_path,
+                        &destination,
+                        decision == Decision::Overwrite,
+                    );
+                    match result {
+                        Ok(destination) => {
+                            match action.kind {
+                                ActionKind::Move => self.total_files_moved += 1,
+                                ActionKind::Copy => self.total_files_copied += 1,
+                                ActionKind::Symlink | ActionKind::Hardlink => {
+                                    self.total_files_linked += 1
+                                }
+                                ActionKind::Trash => self.total_files_trashed += 1,
+                                ActionKind::Delete => self.total_files_deleted += 1,
+                            }
+                            let outcome = match decision {
+                                Decision::Overwrite => {
+                                    self.total_files_overwritten += 1;
+                                    Outcome::Overwritten
+                                }
+                                Decision::Rename => {
+                                    self.total_files_renamed += 1;
+                                    Outcome::Renamed
+                                }
+                                _ => Outcome::Completed,
+                            };
+                            if destination.as_os_str().is_empty() {
+                                println!(
+                                    "{} file {}",
+                                    action.kind.past_tense(),
+                                    source_path.display()
+                                );
+                            } else {
+                                println!(
+                                    "{} file {} to {}",
+                                    action.kind.past_tense(),
+                                    source_path.display(),
+                                    destination.display()
+                                );
+                            }
+                            JournalEntry::new(action.kind, source_path, &destination, outcome)
+                        }
+                        Err(err) => {
+                            // increment error counter
+                            self.total_files_errors += 1;
+                            error!(
+                                "An error occurred while applying {} to file {}: {}",
+                                action.kind,
+                                source_path.display(),
+                                err
+                            );
+                            JournalEntry::failed(action.kind, source_path, &destination, &err)
+                        }
+                    }
                 }
             };
+
+            if let Some(journal) = &mut self.journal {
+                journal.record(&entry);
+            }
         }
     }
 
-    /// Exports actions to a plain text file
-    pub fn export_actions(&self, file_name: &str) -> Result<()> {
-        println!("Writing action plan to file: {}", file_name);
+    /// Records every executed action into the journal, so that the run can be undone
+    pub fn attach_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
 
-        let mut file = fs::OpenOptions::new()
-            .append(true)
-            .create_new(true)
-            .open(file_name)?;
-        for (source, destination) in &self.actions {
-            file.write_all(
-                format!(
-                    "\"{}\" -> \"{}\"\n",
-                    source.display(),
-                    destination.display()
-                )
-                .as_bytes(),
-            )?;
+    /// Hands the journal back, so that it can be continued by other actions
+    pub fn detach_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    pub fn get_journal_path(&self) -> Option<&Path> {
+        self.journal.as_ref().map(|journal| journal.get_path())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Removes the source directories below `root` which were left empty by the
+    /// executed actions, `root` itself is never removed.
+    pub fn prune_empty_directories(&mut self, root: &Path) {
+        let mut candidates = BTreeSet::new();
+        for action in &self.actions {
+            if action.source.exists() {
+                continue;
+            }
+
+            let mut directory = action.source.parent();
+            while let Some(parent) = directory {
+                if parent == root || !parent.starts_with(root) {
+                    break;
+                }
+                candidates.insert(parent.to_path_buf());
+                directory = parent.parent();
+            }
         }
 
-        Ok(())
+        // Deepest directories first, so that parents are emptied before they are visited
+        for directory in candidates.iter().rev() {
+            let is_empty = fs::read_dir(directory).is_ok_and(|mut d| d.next().is_none());
+            if !is_empty {
+                continue;
+            }
+
+            match fs::remove_dir(directory) {
+                Ok(()) => {
+                    self.total_directories_pruned += 1;
+                    println!("Removed empty directory {}", directory.display());
+                }
+                Err(err) => error!(
+                    "An error occurred while removing directory {}: {}",
+                    directory.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    /// Exports actions to a structured plan file, see [`plan::write_plan`]
+    pub fn export_actions(&self, file_name: &str) -> Result<()> {
+        println!("Writing action plan to file: {}", file_name);
+        plan::write_plan(file_name, &self.header, &self.actions)
     }
 
     pub fn get_total_files_scanned(&self) -> u32 {
@@ -114,11 +645,296 @@ impl Actions {
         self.total_files_moved
     }
 
+    pub fn get_total_files_copied(&self) -> u32 {
+        self.total_files_copied
+    }
+
+    pub fn get_total_files_linked(&self) -> u32 {
+        self.total_files_linked
+    }
+
+    pub fn get_total_files_trashed(&self) -> u32 {
+        self.total_files_trashed
+    }
+
+    pub fn get_total_files_deleted(&self) -> u32 {
+        self.total_files_deleted
+    }
+
     pub fn get_total_files_skipped(&self) -> u32 {
         self.total_files_skipped
     }
 
+    pub fn get_total_files_deferred(&self) -> u32 {
+        self.total_files_deferred
+    }
+
+    pub fn get_total_files_ignored(&self) -> u32 {
+        self.total_files_ignored
+    }
+
     pub fn get_total_files_errors(&self) -> u32 {
         self.total_files_errors
     }
+
+    pub fn get_total_files_renamed(&self) -> u32 {
+        self.total_files_renamed
+    }
+
+    pub fn get_total_files_overwritten(&self) -> u32 {
+        self.total_files_overwritten
+    }
+
+    pub fn get_total_duplicates_removed(&self) -> u32 {
+        self.total_duplicates_removed
+    }
+
+    pub fn get_total_conflicts_skipped(&self) -> u32 {
+        self.total_conflicts_skipped
+    }
+
+    pub fn get_total_files_stale(&self) -> u32 {
+        self.total_files_stale
+    }
+
+    pub fn get_total_directories_pruned(&self) -> u32 {
+        self.total_directories_pruned
+    }
+
+    /// Adds the statistics of other actions, e.g. to combine the runs of several profiles
+    pub fn add_statistics(&mut self, other: &Actions) {
+        self.total_files_scanned += other.total_files_scanned;
+        self.total_files_moved += other.total_files_moved;
+        self.total_files_copied += other.total_files_copied;
+        self.total_files_linked += other.total_files_linked;
+        self.total_files_trashed += other.total_files_trashed;
+        self.total_files_deleted += other.total_files_deleted;
+        self.total_files_skipped += other.total_files_skipped;
+        self.total_files_deferred += other.total_files_deferred;
+        self.total_files_ignored += other.total_files_ignored;
+        self.total_files_errors += other.total_files_errors;
+        self.total_files_renamed += other.total_files_renamed;
+        self.total_files_overwritten += other.total_files_overwritten;
+        self.total_duplicates_removed += other.total_duplicates_removed;
+        self.total_conflicts_skipped += other.total_conflicts_skipped;
+        self.total_files_stale += other.total_files_stale;
+        self.total_directories_pruned += other.total_directories_pruned;
+    }
+}
+
+/// Applies the action kind to the source file, `replace` removes an existing destination
+/// first. Returns where the file ended up, the trash location for trashed files and an
+/// empty path for deleted files.
+fn apply_action(
+    kind: ActionKind,
+    source: &Path,
+    destination: &Path,
+    replace: bool,
+) -> Result<PathBuf> {
+    if kind.has_destination() {
+        if let Some(parent_dir) = destination.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        // Renaming replaces the destination by itself, links are never created over a file
+        if replace && kind != ActionKind::Move {
+            fs::remove_file(destination)?;
+        }
+    }
+
+    match kind {
+        ActionKind::Move => move_file_safely(source, destination)?,
+        ActionKind::Copy => {
+            fs::copy(source, destination)?;
+        }
+        ActionKind::Symlink => symlink(&path::absolute(source)?, destination)?,
+        ActionKind::Hardlink => fs::hard_link(source, destination)?,
+        ActionKind::Trash => return trash::trash_file(source),
+        ActionKind::Delete => {
+            fs::remove_file(source)?;
+            return Ok(PathBuf::new());
+        }
+    }
+
+    Ok(destination.to_path_buf())
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_original: &Path, _link: &Path) -> Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symbolic links are only supported on unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn action(kind: ActionKind, source: &str, destination: &str) -> Action {
+        Action {
+            kind,
+            source: PathBuf::from(source),
+            destination: PathBuf::from(destination),
+            on_conflict: ConflictStrategy::default(),
+            decision: Decision::default(),
+            size: None,
+            modified: None,
+            hash: None,
+            allow_absolute: false,
+            stale: false,
+        }
+    }
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "fsorg-actions-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::canonicalize(directory).unwrap()
+    }
+
+    #[test]
+    fn destinations_escape_once_normalised() {
+        let root = Path::new("/fsorg-missing/destination");
+        let escapes = |destination: &str| {
+            action(ActionKind::Move, "/source/a.txt", destination).escapes(root)
+        };
+        assert!(!escapes("/fsorg-missing/destination/Docs/a.txt"));
+        assert!(!escapes("/fsorg-missing/destination/Docs/../a.txt"));
+        assert!(!escapes("/fsorg-missing/destination/./Docs/a.txt"));
+        assert!(escapes("/fsorg-missing/destination/Docs/../../a.txt"));
+        assert!(escapes("/fsorg-missing/destination/../../etc/passwd"));
+        assert!(escapes("/fsorg-missing/destination-other/a.txt"));
+        assert!(escapes("/etc/passwd"));
+    }
+
+    #[test]
+    fn destinations_escape_without_a_root_unless_allowed() {
+        let mut moved = action(ActionKind::Move, "/source/a.txt", "/destination/a.txt");
+        assert!(moved.escapes(Path::new("")));
+        assert!(moved.escapes(Path::new("/elsewhere")));
+
+        moved.allow_absolute = true;
+        assert!(!moved.escapes(Path::new("")));
+        assert!(!moved.escapes(Path::new("/elsewhere")));
+
+        // Nothing is placed anywhere by the actions without a destination
+        let trashed = action(ActionKind::Trash, "/source/a.txt", "");
+        assert!(!trashed.escapes(Path::new("")));
+        assert!(!trashed.escapes(Path::new("/elsewhere")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn destinations_escape_through_symlinked_parents() {
+        let directory = temporary_directory("symlinked-parents");
+        let root = directory.join("destination");
+        let outside = directory.join("outside");
+        fs::create_dir_all(root.join("Docs")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("Link")).unwrap();
+        std::os::unix::fs::symlink(&root, directory.join("root-link")).unwrap();
+
+        let escapes = |destination: PathBuf, root: &Path| {
+            action(
+                ActionKind::Copy,
+                "/source/a.txt",
+                &destination.to_string_lossy(),
+            )
+            .escapes(root)
+        };
+        assert!(!escapes(root.join("Docs/a.txt"), &root));
+        assert!(!escapes(root.join("Docs/New/Deeper/a.txt"), &root));
+        assert!(escapes(root.join("Link/a.txt"), &root));
+        assert!(escapes(root.join("Link/New/a.txt"), &root));
+        // A root reached through a symbolic link is still the same root
+        let linked_root = directory.join("root-link");
+        assert!(!escapes(linked_root.join("Docs/a.txt"), &linked_root));
+        assert!(escapes(linked_root.join("Link/a.txt"), &linked_root));
+
+        // Executing refuses the escaping action and leaves the source in place
+        let source = directory.join("a.txt");
+        fs::write(&source, "a").unwrap();
+        let mut actions = Actions::new();
+        actions.header.destination_root = root.clone();
+        actions.add_action(action(
+            ActionKind::Move,
+            &source.to_string_lossy(),
+            &root.join("Link/a.txt").to_string_lossy(),
+        ));
+        actions.execute_actions();
+        assert_eq!(actions.get_total_files_errors(), 1);
+        assert_eq!(actions.get_total_files_moved(), 0);
+        assert!(source.exists());
+        assert!(!outside.join("a.txt").exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn confines_plans_to_the_given_destination_root() {
+        let mut actions = Actions::new();
+        actions.header.source_root = PathBuf::from("/source");
+        actions.header.destination_root = PathBuf::from("/");
+        let mut allowed = action(ActionKind::Move, "/source/Music/a.mp3", "/srv/a.mp3");
+        allowed.allow_absolute = true;
+        let mut forged = action(ActionKind::Move, "/source/b.txt", "/etc/b.txt");
+        forged.allow_absolute = true;
+        let outside = action(ActionKind::Move, "/source/c.txt", "/etc/c.txt");
+        let inside = action(ActionKind::Move, "/source/d.txt", "/destination/d.txt");
+        for action in [allowed, forged, outside, inside] {
+            actions.add_action(action);
+        }
+
+        let asked = RefCell::new(vec![]);
+        actions.confine(PathBuf::from("/destination"), |source, relative| {
+            asked
+                .borrow_mut()
+                .push((source.to_path_buf(), relative.to_path_buf()));
+            relative == Path::new("Music/a.mp3")
+        });
+
+        // Only the actions claiming to leave the root are checked again
+        assert_eq!(
+            asked.into_inner(),
+            [
+                (
+                    PathBuf::from("/source/Music/a.mp3"),
+                    PathBuf::from("Music/a.mp3")
+                ),
+                (PathBuf::from("/source/b.txt"), PathBuf::from("b.txt")),
+            ]
+        );
+        let root = &actions.header.destination_root;
+        assert_eq!(root, Path::new("/destination"));
+        let escaping: Vec<bool> = actions.actions.iter().map(|a| a.escapes(root)).collect();
+        assert_eq!(escaping, [false, true, true, false]);
+    }
+
+    #[test]
+    fn confines_legacy_plans_by_file_name() {
+        let mut actions = Actions::new();
+        let mut legacy = action(ActionKind::Move, "/source/Music/a.mp3", "/srv/a.mp3");
+        legacy.allow_absolute = true;
+        actions.add_action(legacy);
+        assert!(actions.is_legacy());
+
+        let asked = RefCell::new(vec![]);
+        actions.confine(PathBuf::from("/destination"), |_, relative| {
+            asked.borrow_mut().push(relative.to_path_buf());
+            false
+        });
+        assert_eq!(asked.into_inner(), [PathBuf::from("a.mp3")]);
+        assert!(!actions.is_legacy());
+        assert!(actions.actions[0].escapes(&actions.header.destination_root));
+    }
 }
@@ -0,0 +1,496 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+/// A size either in bytes or with a unit, e.g. "10KB", "1.5GiB"
+pub enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+/// Optional predicates of a rule, all of them have to hold for the rule to match
+pub struct Conditions {
+    /// Minimum file size (inclusive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<Size>,
+
+    /// Maximum file size (inclusive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<Size>,
+
+    /// Minimum time since the last modification, e.g. "30d", "12h"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub older_than: Option<String>,
+
+    /// Maximum time since the last modification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newer_than: Option<String>,
+
+    /// Minimum time since the creation of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_older_than: Option<String>,
+
+    /// Maximum time since the creation of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_newer_than: Option<String>,
+
+    /// Whether any of the executable bits has to be set, or none of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub executable: Option<bool>,
+
+    /// Octal permission bits which all have to be set, e.g. "644"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<String>,
+
+    /// User name or uid of the owner
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// Regex matched against the path of the file relative to the working directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_regex: Option<String>,
+}
+
+impl Conditions {
+    pub fn is_empty(&self) -> bool {
+        *self == Conditions::default()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Conditions with their values parsed, ready for matching
+pub struct CompiledConditions {
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    older_than: Option<Duration>,
+    newer_than: Option<Duration>,
+    created_older_than: Option<Duration>,
+    created_newer_than: Option<Duration>,
+    executable: Option<bool>,
+    permissions: Option<u32>,
+    owner: Option<u32>,
+    path_regex: Option<Regex>,
+}
+
+impl CompiledConditions {
+    pub fn compile(conditions: &Conditions) -> Result<Self, String> {
+        let duration = |value: &Option<String>| value.as_deref().map(parse_duration).transpose();
+
+        Ok(CompiledConditions {
+            min_size: conditions.min_size.as_ref().map(parse_size).transpose()?,
+            max_size: conditions.max_size.as_ref().map(parse_size).transpose()?,
+            older_than: duration(&conditions.older_than)?,
+            newer_than: duration(&conditions.newer_than)?,
+            created_older_than: duration(&conditions.created_older_than)?,
+            created_newer_than: duration(&conditions.created_newer_than)?,
+            executable: conditions.executable,
+            permissions: conditions
+                .permissions
+                .as_deref()
+                .map(|mode| {
+                    u32::from_str_radix(mode, 8)
+                        .ok()
+                        .filter(|mode| *mode <= 0o7777)
+                        .ok_or_else(|| format!("invalid octal permissions: {}", mode))
+                })
+                .transpose()?,
+            owner: conditions.owner.as_deref().map(resolve_owner).transpose()?,
+            path_regex: conditions
+                .path_regex
+                .as_deref()
+                .map(|pattern| {
+                    Regex::new(pattern)
+                        .map_err(|err| format!("invalid path regex {}: {}", pattern, err))
+                })
+                .transpose()?,
+        })
+    }
+
+    /// Checks the conditions for a file, `relative_path` is the path of the file relative to
+    /// the working directory. Conditions on the metadata never hold without metadata.
+    pub fn matches(&self, relative_path: &Path, metadata: Option<&Metadata>) -> bool {
+        self.evaluate(relative_path, metadata)
+            .iter()
+            .all(|(_, held)| *held)
+    }
+
+    /// Checks every set condition on its own, returning the name of each condition along
+    /// with whether it held for the file.
+    pub fn evaluate(
+        &self,
+        relative_path: &Path,
+        metadata: Option<&Metadata>,
+    ) -> Vec<(&'static str, bool)> {
+        let mut results = vec![];
+        if let Some(regex) = &self.path_regex {
+            // Always matched with forward slashes, independent of the platform
+            let relative_path = relative_path.to_string_lossy().replace('\\', "/");
+            results.push(("path_regex", regex.is_match(&relative_path)));
+        }
+
+        let size = metadata.map(|metadata| metadata.len());
+        let modified_age = metadata.and_then(|metadata| metadata.modified().ok().map(age));
+        let created_age = metadata.and_then(|metadata| metadata.created().ok().map(age));
+
+        if let Some(min) = self.min_size {
+            results.push(("min_size", size.is_some_and(|size| size >= min)));
+        }
+        if let Some(max) = self.max_size {
+            results.push(("max_size", size.is_some_and(|size| size <= max)));
+        }
+        if let Some(min) = self.older_than {
+            results.push(("older_than", modified_age.is_some_and(|age| age >= min)));
+        }
+        if let Some(max) = self.newer_than {
+            results.push(("newer_than", modified_age.is_some_and(|age| age <= max)));
+        }
+        if let Some(min) = self.created_older_than {
+            results.push((
+                "created_older_than",
+                created_age.is_some_and(|age| age >= min),
+            ));
+        }
+        if let Some(max) = self.created_newer_than {
+            results.push((
+                "created_newer_than",
+                created_age.is_some_and(|age| age <= max),
+            ));
+        }
+        if let Some(executable) = self.executable {
+            results.push((
+                "executable",
+                metadata.is_some_and(|metadata| is_executable(metadata) == executable),
+            ));
+        }
+        if let Some(bits) = self.permissions {
+            results.push((
+                "permissions",
+                metadata
+                    .and_then(mode)
+                    .is_some_and(|mode| mode & bits == bits),
+            ));
+        }
+        if let Some(owner) = self.owner {
+            results.push((
+                "owner",
+                metadata.and_then(uid).is_some_and(|uid| uid == owner),
+            ));
+        }
+
+        results
+    }
+}
+
+/// Time elapsed since the given time, files from the future have an age of zero
+fn age(time: SystemTime) -> Duration {
+    SystemTime::now().duration_since(time).unwrap_or_default()
+}
+
+/// Parses a size like 1024, "10KB" (decimal), "10KiB" or "10K" (binary)
+fn parse_size(size: &Size) -> Result<u64, String> {
+    let text = match size {
+        Size::Bytes(bytes) => return Ok(*bytes),
+        Size::Text(text) => text.trim(),
+    };
+
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {}", text))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        _ => return Err(format!("invalid size unit: {}", text)),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a duration like "30d", "12h", "2w", "45m" or "10s"
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", duration))?;
+    let seconds = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in {}, expected one of s, m, h, d, w",
+                duration
+            ));
+        }
+    };
+
+    let seconds = number
+        .checked_mul(seconds)
+        .ok_or_else(|| format!("duration too large: {}", duration))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn uid(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn uid(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+fn is_executable(metadata: &Metadata) -> bool {
+    mode(metadata).is_some_and(|mode| mode & 0o111 != 0)
+}
+
+/// Resolves a user name through /etc/passwd, numeric values are taken as uid
+fn resolve_owner(owner: &str) -> Result<u32, String> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(uid);
+    }
+
+    fs::read_to_string("/etc/passwd")
+        .ok()
+        .and_then(|passwd| {
+            passwd.lines().find_map(|line| {
+                let mut fields = line.split(':');
+                match (fields.next(), fields.nth(1)) {
+                    (Some(name), Some(uid)) if name == owner => uid.parse().ok(),
+                    _ => None,
+                }
+            })
+        })
+        .ok_or_else(|| format!("unknown owner: {}", owner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(
+            parse_duration(" 2w "),
+            Ok(Duration::from_secs(2 * 7 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_duration("0s"), Ok(Duration::ZERO));
+        assert!(parse_duration("5q").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_durations() {
+        assert_eq!(
+            parse_duration("99999999999999999w"),
+            Err("duration too large: 99999999999999999w".to_string())
+        );
+        assert!(parse_duration("99999999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        let size = |text: &str| parse_size(&Size::Text(text.to_string()));
+        assert_eq!(parse_size(&Size::Bytes(512)), Ok(512));
+        assert_eq!(size("512"), Ok(512));
+        assert_eq!(size("512B"), Ok(512));
+        assert_eq!(size("10K"), Ok(10 * 1024));
+        assert_eq!(size("10KiB"), Ok(10 * 1024));
+        assert_eq!(size("10KB"), Ok(10_000));
+        assert_eq!(size("10kb"), Ok(10_000));
+        assert_eq!(size(" 1.5MiB "), Ok(1024 * 1024 * 3 / 2));
+        assert_eq!(size("1.5 GB"), Ok(1_500_000_000));
+        assert_eq!(size("2T"), Ok(2 << 40));
+        assert_eq!(size("10XB"), Err("invalid size unit: 10XB".to_string()));
+        assert_eq!(size("10KBs"), Err("invalid size unit: 10KBs".to_string()));
+        assert_eq!(size("KB"), Err("invalid size: KB".to_string()));
+        assert_eq!(size("1.2.3K"), Err("invalid size: 1.2.3K".to_string()));
+        assert_eq!(size("-1K"), Err("invalid size: -1K".to_string()));
+    }
+
+    /// Evaluates the conditions for a file of 2 KiB modified an hour ago, with the given
+    /// permissions on unix
+    fn evaluate(conditions: Conditions, relative_path: &str) -> Vec<(&'static str, bool)> {
+        let compiled = CompiledConditions::compile(&conditions).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "fsorg-conditions-test-{}-{}",
+            std::process::id(),
+            relative_path.replace(['/', '\\'], "_")
+        ));
+        fs::write(&path, vec![0; 2048]).unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60 * 60))
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o640))
+                .unwrap();
+        }
+        let metadata = fs::metadata(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        compiled.evaluate(Path::new(relative_path), Some(&metadata))
+    }
+
+    #[test]
+    fn evaluates_size_conditions() {
+        let sizes = |min: &str, max: &str| Conditions {
+            min_size: Some(Size::Text(min.to_string())),
+            max_size: Some(Size::Text(max.to_string())),
+            ..Default::default()
+        };
+        assert_eq!(
+            evaluate(sizes("2K", "2K"), "exact.bin"),
+            [("min_size", true), ("max_size", true)]
+        );
+        assert_eq!(
+            evaluate(sizes("2KB", "3KB"), "decimal.bin"),
+            [("min_size", true), ("max_size", true)]
+        );
+        assert_eq!(
+            evaluate(sizes("3K", "1M"), "small.bin"),
+            [("min_size", false), ("max_size", true)]
+        );
+        assert_eq!(
+            evaluate(sizes("0", "1K"), "large.bin"),
+            [("min_size", true), ("max_size", false)]
+        );
+    }
+
+    #[test]
+    fn evaluates_age_conditions() {
+        let ages = |older_than: &str, newer_than: &str| Conditions {
+            older_than: Some(older_than.to_string()),
+            newer_than: Some(newer_than.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            evaluate(ages("30m", "2h"), "recent.txt"),
+            [("older_than", true), ("newer_than", true)]
+        );
+        assert_eq!(
+            evaluate(ages("1d", "30m"), "old.txt"),
+            [("older_than", false), ("newer_than", false)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn evaluates_permission_conditions() {
+        let permissions = |executable: bool, bits: &str| Conditions {
+            executable: Some(executable),
+            permissions: Some(bits.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            evaluate(permissions(false, "640"), "exact.sh"),
+            [("executable", true), ("permissions", true)]
+        );
+        assert_eq!(
+            evaluate(permissions(false, "600"), "subset.sh"),
+            [("executable", true), ("permissions", true)]
+        );
+        assert_eq!(
+            evaluate(permissions(true, "644"), "other.sh"),
+            [("executable", false), ("permissions", false)]
+        );
+        for invalid in ["9", "10000", "rw"] {
+            let conditions = Conditions {
+                permissions: Some(invalid.to_string()),
+                ..Default::default()
+            };
+            assert!(
+                CompiledConditions::compile(&conditions).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn evaluates_path_regexes_with_forward_slashes() {
+        let conditions = || Conditions {
+            path_regex: Some("^Projects/[^/]+/build/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            evaluate(conditions(), "Projects/fsorg/build/out.o"),
+            [("path_regex", true)]
+        );
+        assert_eq!(
+            evaluate(conditions(), "Projects\\fsorg\\build\\out.o"),
+            [("path_regex", true)]
+        );
+        assert_eq!(
+            evaluate(conditions(), "Projects/build/out.o"),
+            [("path_regex", false)]
+        );
+        assert!(
+            CompiledConditions::compile(&Conditions {
+                path_regex: Some("(".to_string()),
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn metadata_conditions_never_hold_without_metadata() {
+        let compiled = CompiledConditions::compile(&Conditions {
+            min_size: Some(Size::Bytes(0)),
+            older_than: Some("0s".to_string()),
+            executable: Some(false),
+            path_regex: Some("txt$".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            compiled.evaluate(Path::new("a.txt"), None),
+            [
+                ("path_regex", true),
+                ("min_size", false),
+                ("older_than", false),
+                ("executable", false),
+            ]
+        );
+        assert!(!compiled.matches(Path::new("a.txt"), None));
+        assert!(CompiledConditions::default().matches(Path::new("a.txt"), None));
+    }
+}
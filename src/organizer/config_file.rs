@@ -0,0 +1,534 @@
+use super::configurations::Configurations;
+use super::journal::unix_timestamp;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value, json};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Latest version of the configurations schema
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Directory of the configurations shared by all the users of the system
+pub const SYSTEM_CONFIG_DIRECTORY: &str = "/etc/fsorg";
+
+/// Name of the configurations file of the system and the user, without extension
+pub const CONFIG_FILE_STEM: &str = "config";
+
+/// Name of the configurations file of a single source directory, placed inside of it,
+/// without extension
+pub const DIRECTORY_CONFIG_STEM: &str = ".fsorg";
+
+/// Settings a directory configurations file may hold. The working directory may hold
+/// files from untrusted sources, so where the files go, what happens to existing files
+/// and the safeguards are only taken from the system and user configurations.
+pub const DIRECTORY_SETTINGS: [&str; 4] = ["version", "rules", "ignore", "scan"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Format of a configurations file, detected by its extension
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Extensions of the supported formats, in the order they are looked up
+    pub const EXTENSIONS: [&str; 4] = ["json", "toml", "yaml", "yml"];
+
+    /// The format of a file by its extension, JSON unless it is `.toml`, `.yaml` or `.yml`
+    pub fn of(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    /// Parses the content of a file, errors are reported along with their position
+    pub fn parse<T: DeserializeOwned>(&self, path: &Path, contents: &str) -> Result<T, String> {
+        let (message, position) = match self {
+            ConfigFormat::Json => match serde_json::from_str(contents) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    // The position is reported separately
+                    let position = format!(" at line {} column {}", err.line(), err.column());
+                    let message = err.to_string();
+                    (
+                        message
+                            .strip_suffix(&position)
+                            .unwrap_or(&message)
+                            .to_string(),
+                        Some((err.line(), err.column())),
+                    )
+                }
+            },
+            ConfigFormat::Toml => match toml::from_str(contents) {
+                Ok(value) => return Ok(value),
+                Err(err) => (
+                    err.message().to_string(),
+                    err.span().map(|span| line_column(contents, span.start)),
+                ),
+            },
+            ConfigFormat::Yaml => match serde_yaml::from_str(contents) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    let message = err.to_string();
+                    match err.location() {
+                        Some(location) => {
+                            let position = format!(
+                                " at line {} column {}",
+                                location.line(),
+                                location.column()
+                            );
+                            (
+                                message
+                                    .strip_suffix(&position)
+                                    .unwrap_or(&message)
+                                    .to_string(),
+                                Some((location.line(), location.column())),
+                            )
+                        }
+                        None => (message, None),
+                    }
+                }
+            },
+        };
+
+        Err(match position {
+            Some((line, column)) => format!(
+                "Unable to parse the configurations file {} at line {}, column {}: {}",
+                path.display(),
+                line,
+                column,
+                message
+            ),
+            None => format!(
+                "Unable to parse the configurations file {}: {}",
+                path.display(),
+                message
+            ),
+        })
+    }
+
+    /// Serializes the configurations in the format
+    pub fn serialize(&self, config: &Value) -> Result<String, String> {
+        match self {
+            ConfigFormat::Json => {
+                serde_json::to_string_pretty(config).map_err(|err| err.to_string())
+            }
+            // TOML has no null, an unset value is left out instead
+            ConfigFormat::Toml => {
+                toml::to_string_pretty(&without_nulls(config)).map_err(|err| err.to_string())
+            }
+            ConfigFormat::Yaml => serde_yaml::to_string(config).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// The configurations file `<stem>.<extension>` of a directory in the first of the
+/// supported formats which exists, defaulting to JSON
+pub fn find_config_file(directory: &Path, stem: &str) -> PathBuf {
+    ConfigFormat::EXTENSIONS
+        .iter()
+        .map(|extension| directory.join(format!("{}.{}", stem, extension)))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| directory.join(format!("{}.json", stem)))
+}
+
+/// Removes the null values of objects, recursively
+pub fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(without_nulls).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Line and column of a byte offset, both starting at 1
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Where configurations come from, in increasing precedence
+pub enum Layer {
+    System,
+    #[default]
+    User,
+    Directory,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Layer::System => "system",
+            Layer::User => "user",
+            Layer::Directory => "directory",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+/// The configurations file of a layer, migrated to the latest schema version
+pub struct LayerFile {
+    pub layer: Layer,
+    pub path: PathBuf,
+
+    /// Content of the file, none if the file does not exist or is empty
+    pub config: Option<Map<String, Value>>,
+
+    /// Schema version the file was migrated from while reading
+    pub migrated_from: Option<u32>,
+}
+
+impl LayerFile {
+    /// Reads and validates the configurations file of a layer in the format of its
+    /// extension, older schema versions are migrated in memory. Errors are reported
+    /// along with their position in the file.
+    pub fn read(layer: Layer, path: &Path) -> Result<Self, String> {
+        let mut layer_file = LayerFile {
+            layer,
+            path: path.to_path_buf(),
+            config: None,
+            migrated_from: None,
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) if contents.trim().is_empty() => return Ok(layer_file),
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(layer_file),
+            Err(err) => {
+                return Err(format!(
+                    "Unable to read the configurations file {}: {}",
+                    path.display(),
+                    err
+                ));
+            }
+        };
+
+        let format = ConfigFormat::of(path);
+        let config: Value = format.parse(path, &contents)?;
+        let version = schema_version(&config)
+            .map_err(|err| format!("Invalid configurations file {}: {}", path.display(), err))?;
+        if version > CONFIG_VERSION {
+            return Err(format!(
+                "The configurations file {} has version {}, which is newer than the supported version {}, please upgrade fsorg",
+                path.display(),
+                version,
+                CONFIG_VERSION
+            ));
+        }
+
+        let config = if version < CONFIG_VERSION {
+            let mut config = config;
+            for from in version..CONFIG_VERSION {
+                migrate(&mut config, from).map_err(|err| {
+                    format!(
+                        "Unable to migrate the configurations file {} from version {}: {}",
+                        path.display(),
+                        from,
+                        err
+                    )
+                })?;
+            }
+            serde_json::from_value::<Configurations>(config.clone()).map_err(|err| {
+                format!(
+                    "Unable to parse the configurations file {}: {}",
+                    path.display(),
+                    err
+                )
+            })?;
+            if layer == Layer::User {
+                warn!(
+                    "Migrated the configurations file {} from version {} in memory, rewrite it with: fsorg config migrate",
+                    path.display(),
+                    version
+                );
+            } else {
+                warn!(
+                    "Migrated the {} configurations file {} from version {} in memory, it should be updated to version {}",
+                    layer,
+                    path.display(),
+                    version,
+                    CONFIG_VERSION
+                );
+            }
+            layer_file.migrated_from = Some(version);
+            config
+        } else {
+            // Parsed again for the position of errors
+            format.parse::<Configurations>(path, &contents)?;
+            config
+        };
+
+        match config {
+            Value::Object(config) => layer_file.config = Some(config),
+            _ => {
+                return Err(format!(
+                    "Invalid configurations file {}: not an object",
+                    path.display()
+                ));
+            }
+        }
+        Ok(layer_file)
+    }
+
+    /// Fails if a directory configurations file holds settings other than
+    /// `DIRECTORY_SETTINGS`
+    pub fn check_directory_settings(&self) -> Result<(), String> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        let refused: Vec<&str> = config
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !DIRECTORY_SETTINGS.contains(key))
+            .collect();
+        if refused.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "The directory configurations file {} cannot set {}, only {} are allowed there",
+            self.path.display(),
+            refused.join(", "),
+            DIRECTORY_SETTINGS.join(", ")
+        ))
+    }
+
+    /// The rules of the file or of one of its profiles, validated while reading.
+    /// None if the profile does not define its own rules.
+    pub fn rules(&self, profile: Option<&str>) -> Option<Value> {
+        let config = self.config.as_ref()?;
+        match profile {
+            Some(profile) => config.get("profiles")?.get(profile)?.get("rules").cloned(),
+            None => Some(config.get("rules").cloned().unwrap_or(Value::Array(vec![]))),
+        }
+    }
+}
+
+/// Merges the settings of the layers, given in increasing precedence. Objects are merged
+/// key by key, any other value of a higher layer replaces the one of the lower layers and
+/// the ignore patterns of all the layers are kept. The rules are left out.
+pub fn merge_settings(layers: &[&LayerFile]) -> Value {
+    let mut merged = Value::Object(Map::new());
+    for config in layers.iter().filter_map(|layer| layer.config.as_ref()) {
+        merge_into(&mut merged, config);
+    }
+    merged
+}
+
+/// Merges settings of a higher precedence into the merged settings, see `merge_settings`
+pub fn merge_into(merged: &mut Value, config: &Map<String, Value>) {
+    for (key, value) in config {
+        match key.as_str() {
+            "rules" | "version" => {}
+            "ignore" => {
+                let patterns = value.as_array().into_iter().flatten().cloned();
+                match &mut merged[key] {
+                    Value::Array(ignore) => ignore.extend(patterns),
+                    ignore => *ignore = Value::Array(patterns.collect()),
+                }
+            }
+            _ => merge_value(&mut merged[key], value),
+        }
+    }
+}
+
+fn merge_value(merged: &mut Value, value: &Value) {
+    match (merged, value) {
+        (Value::Object(merged), Value::Object(value)) => {
+            for (key, value) in value {
+                merge_value(merged.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (merged, value) => *merged = value.clone(),
+    }
+}
+
+/// Schema version of a configurations file. Files written before the schema was
+/// versioned store the version of fsorg instead, e.g. "1.0.1", and count as version 1.
+fn schema_version(config: &Value) -> Result<u32, String> {
+    match config.get("version") {
+        None => Ok(1),
+        Some(Value::String(version)) => version
+            .split('.')
+            .next()
+            .and_then(|major| major.parse().ok())
+            .ok_or_else(|| format!("invalid version {}", version)),
+        Some(version) => Err(format!("invalid version {}", version)),
+    }
+}
+
+/// Upgrades a configurations file from the given schema version to the next one
+fn migrate(config: &mut Value, version: u32) -> Result<(), String> {
+    // Version 1 stored the rules as a map of <file name regex> -> <destination>,
+    // which is turned into an ordered list keeping the order of the file.
+    if version == 1
+        && let Some(Value::Object(rules)) = config.get("rules")
+    {
+        let rules = rules
+            .iter()
+            .map(|(pattern, destination)| match destination.as_str() {
+                Some(destination) => Ok(json!({ "regex": pattern, "destination": destination })),
+                None => Err(format!("destination of rule {} must be a string", pattern)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        config["rules"] = Value::Array(rules);
+    }
+
+    config["version"] = Value::String((version + 1).to_string());
+    Ok(())
+}
+
+/// Copies a configurations file to `<file>.bak-<timestamp>` before it gets replaced
+pub fn back_up(path: &Path) -> Result<PathBuf, String> {
+    let mut backup = path.as_os_str().to_os_string();
+    backup.push(format!(".bak-{}", unix_timestamp()));
+    let backup = PathBuf::from(backup);
+
+    fs::copy(path, &backup).map_err(|err| {
+        format!(
+            "Unable to back up the configurations file {} to {}: {}",
+            path.display(),
+            backup.display(),
+            err
+        )
+    })?;
+    warn!(
+        "Backed up the configurations file {} to {}",
+        path.display(),
+        backup.display()
+    );
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a configurations file written to a temporary location
+    fn read_temporary_file(name: &str, contents: &str) -> Result<LayerFile, String> {
+        let path =
+            std::env::temp_dir().join(format!("fsorg-config-test-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let layer_file = LayerFile::read(Layer::System, &path);
+        let _ = fs::remove_file(&path);
+        layer_file
+    }
+
+    #[test]
+    fn reads_the_schema_version() {
+        assert_eq!(schema_version(&json!({})), Ok(1));
+        assert_eq!(schema_version(&json!({ "version": "1.0.1" })), Ok(1));
+        assert_eq!(schema_version(&json!({ "version": "2" })), Ok(2));
+        assert!(schema_version(&json!({ "version": "x" })).is_err());
+        assert!(schema_version(&json!({ "version": 2 })).is_err());
+    }
+
+    #[test]
+    fn migrates_the_rule_map_to_an_ordered_list() {
+        let mut config: Value = serde_json::from_str(
+            r#"{
+                "version": "1.0.1",
+                "rules": {
+                    "(?i)^.*\\.zip$": "Archives",
+                    "^a.*$": "A",
+                    "(?i)^.*\\.(mp3|wav)$": "Music"
+                }
+            }"#,
+        )
+        .unwrap();
+        migrate(&mut config, 1).unwrap();
+
+        assert_eq!(
+            config,
+            json!({
+                "version": "2",
+                "rules": [
+                    { "regex": "(?i)^.*\\.zip$", "destination": "Archives" },
+                    { "regex": "^a.*$", "destination": "A" },
+                    { "regex": "(?i)^.*\\.(mp3|wav)$", "destination": "Music" }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn migrates_configurations_without_rules() {
+        let mut config = json!({ "on_conflict": "skip" });
+        migrate(&mut config, 1).unwrap();
+        assert_eq!(config, json!({ "on_conflict": "skip", "version": "2" }));
+    }
+
+    #[test]
+    fn rejects_rules_with_invalid_destinations() {
+        let mut config = json!({ "rules": { "^a$": 1 } });
+        assert_eq!(
+            migrate(&mut config, 1),
+            Err("destination of rule ^a$ must be a string".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_and_migrates_older_files() {
+        let layer_file = read_temporary_file(
+            "v1.json",
+            r#"{ "version": "1.0.1", "rules": { "^b$": "B", "^a$": "A" } }"#,
+        )
+        .unwrap();
+        assert_eq!(layer_file.migrated_from, Some(1));
+        assert_eq!(
+            layer_file.rules(None),
+            Some(json!([
+                { "regex": "^b$", "destination": "B" },
+                { "regex": "^a$", "destination": "A" }
+            ]))
+        );
+    }
+
+    #[test]
+    fn refuses_newer_files() {
+        let err =
+            read_temporary_file("v99.json", r#"{ "version": "99", "rules": [] }"#).unwrap_err();
+        assert!(err.contains("newer than the supported version"), "{}", err);
+    }
+
+    #[test]
+    fn reports_the_position_of_parse_errors() {
+        let err = read_temporary_file(
+            "broken.json",
+            "{\n  \"rules\": [\n    {\"regex\": }\n  ]\n}",
+        )
+        .unwrap_err();
+        assert!(err.contains("at line 3, column"), "{}", err);
+
+        let err = read_temporary_file("broken.toml", "version = \"2\"\nrules = [\n").unwrap_err();
+        assert!(err.contains("at line"), "{}", err);
+    }
+
+    #[test]
+    fn treats_empty_files_as_missing() {
+        let layer_file = read_temporary_file("empty.json", "  \n").unwrap();
+        assert!(layer_file.config.is_none());
+        assert_eq!(layer_file.rules(None), None);
+    }
+}
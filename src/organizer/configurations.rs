@@ -1,22 +1,489 @@
+use super::actions::ActionKind;
+use super::conditions::{CompiledConditions, Conditions};
+use super::config_file::{
+    CONFIG_FILE_STEM, CONFIG_VERSION, ConfigFormat, DIRECTORY_CONFIG_STEM, Layer, LayerFile,
+    SYSTEM_CONFIG_DIRECTORY, back_up, find_config_file, merge_into, merge_settings, without_nulls,
+};
+use super::conflicts::ConflictStrategy;
+use super::glob;
+use super::mime;
+use super::safeguards::{SafeguardOptions, Safeguards};
+use super::template::Template;
+use super::{contract_path, expand_path, normalize_path, write_atomically};
 use log::{debug, error, info, warn};
-use regex::Regex;
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, Write};
-use std::path::Path;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, Metadata};
 use std::path::PathBuf;
+use std::path::{self, Component, Path};
 
 const CWD: &str = ".";
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A single organising rule
+pub struct Rule {
+    /// File name regex, an empty regex matches every file name
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub regex: String,
+
+    /// File name glob as an alternative to the regex, e.g. "*.{mp3,wav,ogg,flac}"
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub glob: String,
+
+    /// Match the regex or glob regardless of the case of the file name
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub ignore_case: bool,
+
+    /// MIME types detected from the file content, the detected type has to be one of
+    /// these or start with one of the `mime_prefix` values
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "one_or_many"
+    )]
+    pub mime: Vec<String>,
+
+    /// Prefixes of the MIME type detected from the file content, e.g. "image/"
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "one_or_many"
+    )]
+    pub mime_prefix: Vec<String>,
+
+    /// Directory for the matching files to be stored, relative to the destination directory.
+    /// It may contain placeholders, see [`Template`]. Not used by the trash and delete actions.
+    #[serde(default)]
+    pub destination: String,
+
+    /// What to do with the matching files, moving them by default
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub action: ActionKind,
+
+    /// Allow the destination to be an absolute path or to leave the destination directory
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub allow_absolute: bool,
+
+    /// Rules with a higher priority are evaluated first, rules with equal priority
+    /// are evaluated in the order they appear in the configurations. Default is 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+
+    /// Conflict strategy for this rule, overrides the global strategy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_conflict: Option<ConflictStrategy>,
+
+    /// Additional predicates on the file, which have to hold along with the regex
+    #[serde(default, skip_serializing_if = "Conditions::is_empty")]
+    pub conditions: Conditions,
+
+    /// Configurations file the rule comes from
+    #[serde(skip)]
+    pub layer: Layer,
+}
+
+impl Rule {
+    pub fn new(regex: &str, destination: &str) -> Self {
+        Rule {
+            regex: regex.to_string(),
+            glob: String::new(),
+            ignore_case: false,
+            mime: vec![],
+            mime_prefix: vec![],
+            destination: destination.to_string(),
+            action: ActionKind::default(),
+            allow_absolute: false,
+            priority: None,
+            on_conflict: None,
+            conditions: Conditions::default(),
+            layer: Layer::User,
+        }
+    }
+
+    /// Glob rule matching the file names regardless of their case
+    pub fn glob(glob: &str, destination: &str) -> Self {
+        Rule {
+            glob: glob.to_string(),
+            ignore_case: true,
+            ..Rule::new("", destination)
+        }
+    }
+
+    /// The file name pattern of the rule, either its glob or its regex
+    pub fn pattern(&self) -> &str {
+        if self.glob.is_empty() {
+            &self.regex
+        } else {
+            &self.glob
+        }
+    }
+
+    pub fn get_priority(&self) -> i32 {
+        self.priority.unwrap_or_default()
+    }
+
+    /// Content based rule, evaluated after the file name based rules
+    fn fallback_by_mime(mime_prefix: &[&str], mime: &[&str], destination: &str) -> Self {
+        Rule {
+            mime: mime.iter().map(|m| m.to_string()).collect(),
+            mime_prefix: mime_prefix.iter().map(|p| p.to_string()).collect(),
+            priority: Some(-10),
+            ..Rule::new("", destination)
+        }
+    }
+
+    /// Short human readable description of what the rule matches
+    pub fn describe(&self) -> String {
+        let mut description = vec![];
+        if !self.pattern().is_empty() {
+            description.push(self.pattern().to_string());
+        }
+        if self.ignore_case {
+            description.push("ignore case".to_string());
+        }
+        if !self.mime.is_empty() {
+            description.push(format!("mime: {}", self.mime.join(" | ")));
+        }
+        if !self.mime_prefix.is_empty() {
+            description.push(format!("mime: {}*", self.mime_prefix.join("* | ")));
+        }
+        if description.is_empty() {
+            description.push("*".to_string());
+        }
+        description.join(", ")
+    }
+
+    /// Short human readable description of what the rule does with the matching files
+    pub fn describe_action(&self) -> String {
+        match self.action {
+            ActionKind::Move => self.destination.clone(),
+            kind if kind.has_destination() => format!("{}: {}", kind, self.destination),
+            kind => kind.to_string(),
+        }
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Accepts either a single string or a list of strings
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+#[derive(Debug)]
+/// A rule whose regex has been compiled, ready for matching
+struct CompiledRule {
+    /// What the rule matches and does, as shown when viewing the rules
+    description: String,
+    regex: Regex,
+    mime: Vec<String>,
+    mime_prefix: Vec<String>,
+    destination: Template,
+    action: ActionKind,
+    on_conflict: Option<ConflictStrategy>,
+    conditions: CompiledConditions,
+    allow_absolute: bool,
+}
+
+impl CompiledRule {
+    fn checks_mime(&self) -> bool {
+        !self.mime.is_empty() || !self.mime_prefix.is_empty()
+    }
+}
+
+#[derive(Debug)]
+/// The outcome of matching a file against the rules
+pub struct RuleMatch {
+    /// Directory where the file has to be placed
+    pub destination: PathBuf,
+
+    /// What to do with the file
+    pub action: ActionKind,
+
+    /// How to handle an already existing file at the destination
+    pub on_conflict: ConflictStrategy,
+
+    /// Whether the destination may lie outside of the destination directory
+    pub allow_absolute: bool,
+}
+
+#[derive(Debug)]
+/// How a single rule was evaluated for a file
+pub struct RuleEvaluation {
+    /// What the rule matches and does
+    pub description: String,
+
+    /// Whether the regex or glob matched the file name
+    pub name_matched: bool,
+
+    /// Whether the detected MIME type was accepted, if the rule checks it
+    pub mime_matched: Option<bool>,
+
+    /// Every condition of the rule along with whether it held
+    pub conditions: Vec<(&'static str, bool)>,
+}
+
+impl RuleEvaluation {
+    /// Whether the rule depends on more than the file name, i.e. the content or conditions
+    pub fn is_conditional(&self) -> bool {
+        self.mime_matched.is_some() || !self.conditions.is_empty()
+    }
+
+    pub fn matched(&self) -> bool {
+        self.name_matched
+            && self.mime_matched != Some(false)
+            && self.conditions.iter().all(|(_, held)| *held)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Why a file goes where it goes, with every rule evaluated in evaluation order
+pub struct Explanation {
+    /// MIME type detected from the content, if any rule checks it
+    pub mime_type: Option<&'static str>,
+
+    pub evaluations: Vec<RuleEvaluation>,
+
+    /// Index of the first matching rule in the evaluations
+    pub winner: Option<usize>,
+
+    /// The outcome of the first matching rule
+    pub rule_match: Option<RuleMatch>,
+}
+
+#[derive(Debug)]
+/// Why a rule could not be added or deleted
+pub enum RuleError {
+    /// The rule itself is invalid, e.g. a regex which does not compile
+    Invalid(String),
+    /// The rule cannot be found or changed, or the configurations cannot be written
+    Failed(String),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::Invalid(message) | RuleError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Compiles the regex, destination template and conditions of a rule
+fn compile_rule(rule: &Rule) -> Result<CompiledRule, String> {
+    let pattern = match (rule.regex.is_empty(), rule.glob.is_empty()) {
+        (_, true) => rule.regex.clone(),
+        (true, false) => format!(
+            "^{}$",
+            glob::to_regex(&rule.glob, true).map_err(|err| format!("Invalid glob: {}", err))?
+        ),
+        (false, false) => return Err("A rule cannot have both a regex and a glob".to_string()),
+    };
+    let pattern = if rule.ignore_case {
+        format!("(?i){}", pattern)
+    } else {
+        pattern
+    };
+    let regex =
+        Regex::new(&pattern).map_err(|err| format!("Failed to compile the regex: {}", err))?;
+    if rule.action.has_destination()
+        && !rule.allow_absolute
+        && let Some(reason) = escapes_root(&rule.destination)
+    {
+        return Err(format!(
+            "Destination leaves the destination directory as {}, set allow_absolute to permit it",
+            reason
+        ));
+    }
+    let destination = Template::parse(&rule.destination, Some(&regex))
+        .map_err(|err| format!("Invalid destination: {}", err))?;
+    let conditions = CompiledConditions::compile(&rule.conditions)
+        .map_err(|err| format!("Invalid conditions: {}", err))?;
+
+    Ok(CompiledRule {
+        description: format!("{} -> {}", rule.describe(), rule.describe_action()),
+        regex,
+        mime: rule.mime.clone(),
+        mime_prefix: rule.mime_prefix.clone(),
+        destination,
+        action: rule.action,
+        on_conflict: rule.on_conflict,
+        conditions,
+        allow_absolute: rule.allow_absolute,
+    })
+}
+
+/// Tells why a rule destination leaves the destination directory, if it does
+pub fn escapes_root(destination: &str) -> Option<&'static str> {
+    let mut depth = 0;
+    for component in Path::new(destination).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return Some("it is an absolute path"),
+            Component::ParentDir if depth == 0 => return Some("it climbs above it via .."),
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+    }
+    None
+}
+
+/// Finds a profile in the merged configurations
+fn select_profile(merged: &Value, name: &str) -> Result<Profile, String> {
+    let profiles = merged.get("profiles").and_then(Value::as_object);
+    match profiles.and_then(|profiles| profiles.get(name)) {
+        Some(profile) => serde_json::from_value(profile.clone())
+            .map_err(|err| format!("Invalid profile {}: {}", name, err)),
+        None => Err(format!(
+            "Unknown profile {}, the configurations define: {}",
+            name,
+            match profiles {
+                Some(profiles) if !profiles.is_empty() => {
+                    profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                }
+                _ => "none".to_string(),
+            }
+        )),
+    }
+}
+
+/// Whether both paths lead to the same existing file
+fn same_file(first: &Path, second: &Path) -> bool {
+    match (fs::canonicalize(first), fs::canonicalize(second)) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false,
+    }
+}
+
+/// Checks a detected MIME type against the accepted types and prefixes of a rule,
+/// matching either of them is enough.
+fn mime_matches(detected: Option<&str>, mime: &[String], mime_prefix: &[String]) -> bool {
+    let Some(detected) = detected else {
+        return false;
+    };
+
+    mime.iter().any(|m| m.eq_ignore_ascii_case(detected))
+        || mime_prefix.iter().any(|p| detected.starts_with(p.as_str()))
+}
+
+/// Orders the rules for evaluation, higher priority first while keeping the
+/// configuration order between rules of the same priority.
+fn evaluation_order(rules: &[Rule]) -> Vec<&Rule> {
+    let mut ordered: Vec<&Rule> = rules.iter().collect();
+    ordered.sort_by_key(|rule| std::cmp::Reverse(rule.get_priority()));
+    ordered
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+/// Options controlling how the working directory is scanned
+pub struct ScanOptions {
+    /// Descend into the subdirectories of the working directory
+    pub recursive: bool,
+
+    /// Maximum depth of subdirectories to descend into, unlimited when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+
+    /// Keep the relative subdirectory structure of a file under its rule destination,
+    /// otherwise files are flattened into the rule destination
+    pub keep_structure: bool,
+
+    /// Remove the source subdirectories which became empty after organising
+    pub prune_empty_directories: bool,
+}
+
+#[derive(Debug, Deserialize)]
+/// A named set of rules, directories and options within the configurations, e.g. for
+/// organising the downloads and the camera imports differently
+pub struct Profile {
+    /// Rules of the profile, the rules of the configurations are used when not set
+    #[serde(default)]
+    rules: Option<Vec<Rule>>,
+
+    /// Source directory of the profile, unless given on the command line
+    #[serde(default)]
+    source: Option<String>,
+
+    /// Destination directory of the profile, unless given on the command line
+    #[serde(default)]
+    destination: Option<String>,
+
+    /// Settings overriding those of the configurations, e.g. `scan` or `on_conflict`
+    #[serde(flatten)]
+    settings: Map<String, Value>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 /// Configurations representation for our application
 pub struct Configurations {
-    /// The map represents <file name regex> -> <Directory for this file to be stored>
-    rules: HashMap<String, String>,
+    /// Ordered list of rules, the first matching rule decides the destination of a file
+    #[serde(default)]
+    rules: Vec<Rule>,
 
     #[serde(skip)]
-    compiled_rules: Vec<(Regex, String)>,
+    compiled_rules: Vec<CompiledRule>,
+
+    /// Source directory scanning options
+    #[serde(default)]
+    scan: ScanOptions,
+
+    /// Conflict strategy for rules which do not specify their own
+    #[serde(default)]
+    on_conflict: ConflictStrategy,
+
+    /// Protection of files which are possibly still being written
+    #[serde(default)]
+    safeguards: SafeguardOptions,
+
+    /// Gitignore style patterns of files and directories to leave alone, evaluated
+    /// before the patterns of the ignore file in the working directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ignore: Vec<String>,
+
+    /// Source directory to organise unless given on the command line, a leading `~`
+    /// and environment variables such as `$HOME` are expanded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+
+    /// Destination directory unless given on the command line, expanded as the source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    destination: Option<String>,
+
+    /// Named profiles, see `Profile`
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    profiles: Map<String, Value>,
+
+    /// Lets the rules of the `.fsorg.json` in the working directory delete files. Only
+    /// honoured in the system and user configurations, as anyone who can place files in
+    /// the working directory can place that file too.
+    #[serde(default, skip_serializing_if = "is_default")]
+    directory_rules_may_delete: bool,
+
+    /// The selected profile
+    #[serde(skip)]
+    profile: Option<String>,
+
+    /// The profile whose own rules are in use, if any
+    #[serde(skip)]
+    rules_profile: Option<String>,
 
     /// Working directory path where we have files to be organized.
     /// Default value will be current working directory
@@ -27,24 +494,63 @@ pub struct Configurations {
     #[serde(skip)]
     destination_directory: PathBuf,
 
-    /// Configurations file path
+    /// Whether the working and destination directories were given on the command line,
+    /// they take precedence over those of the profile then
+    #[serde(skip)]
+    directories_given: (bool, bool),
+
+    /// Whether the working directory was given on the command line or taken from the
+    /// configurations, rather than being the current directory by default
+    #[serde(skip)]
+    source_resolved: bool,
+
+    /// Configurations file path of the user
     /// Default value be a current working directory
     #[serde(skip)]
     configuration_file: PathBuf,
 
-    /// Version for future usage
+    /// Schema version of the configurations, see `CONFIG_VERSION`
+    #[serde(default)]
     version: String,
+
+    /// Schema version the user configurations file was migrated from while loading
+    #[serde(skip)]
+    migrated_from: Option<u32>,
+
+    /// The configurations file of every layer, in increasing precedence, along with
+    /// whether it was present
+    #[serde(skip)]
+    layers: Vec<(Layer, PathBuf, bool)>,
+
+    /// Content of the user configurations file, which is the only one written back
+    #[serde(skip)]
+    user_config: Map<String, Value>,
 }
 
 impl Configurations {
     pub fn new() -> Self {
         Configurations {
-            rules: HashMap::new(),
+            rules: vec![],
             compiled_rules: vec![],
+            scan: ScanOptions::default(),
+            on_conflict: ConflictStrategy::default(),
+            safeguards: SafeguardOptions::default(),
+            ignore: vec![],
+            source: None,
+            destination: None,
+            profiles: Map::new(),
+            directory_rules_may_delete: false,
+            profile: None,
+            rules_profile: None,
             working_directory: CWD.into(),
             destination_directory: CWD.into(),
+            directories_given: (false, false),
+            source_resolved: false,
             configuration_file: PathBuf::new(),
-            version: env!("CARGO_PKG_VERSION").to_string(),
+            version: CONFIG_VERSION.to_string(),
+            migrated_from: None,
+            layers: vec![],
+            user_config: Map::new(),
         }
     }
 
@@ -54,166 +560,851 @@ impl Configurations {
 
     pub fn set_working_directory(&mut self, directory: PathBuf) {
         self.working_directory = directory;
+        self.directories_given.0 = true;
+        self.source_resolved = true;
+    }
+
+    /// The working directory, if it was given or configured rather than the current directory
+    pub fn get_source_directory(&self) -> Option<&PathBuf> {
+        self.source_resolved.then_some(&self.working_directory)
     }
 
     pub fn set_destination_directory(&mut self, directory: PathBuf) {
         self.destination_directory = directory;
+        self.directories_given.1 = true;
+    }
+
+    /// Selects the profile to load the configurations with
+    pub fn set_profile(&mut self, profile: &str) {
+        self.profile = Some(profile.to_string());
+    }
+
+    pub fn get_profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Names of the profiles, in the order they are defined
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    pub fn get_scan_options(&self) -> &ScanOptions {
+        &self.scan
+    }
+
+    pub fn get_scan_options_mut(&mut self) -> &mut ScanOptions {
+        &mut self.scan
+    }
+
+    pub fn get_safeguard_options(&self) -> &SafeguardOptions {
+        &self.safeguards
+    }
+
+    pub fn get_safeguard_options_mut(&mut self) -> &mut SafeguardOptions {
+        &mut self.safeguards
+    }
+
+    pub fn get_ignore_patterns(&self) -> &[String] {
+        &self.ignore
     }
 
-    pub fn view_rules(&self) -> Vec<(String, String)> {
-        self.rules
+    pub fn get_destination_directory(&self) -> &PathBuf {
+        &self.destination_directory
+    }
+
+    /// Hash of the stored configurations, identifies the configurations a plan was generated with
+    pub fn hash(&self) -> String {
+        let serialized = serde_json::to_vec(self).unwrap_or_default();
+        format!("{:x}", Sha256::digest(serialized))
+    }
+
+    pub fn set_conflict_strategy(&mut self, strategy: ConflictStrategy) {
+        self.on_conflict = strategy;
+    }
+
+    /// Returns the directories the rules are placing files into, these must not be
+    /// scanned again when the destination lies inside the working directory.
+    pub fn rule_destination_directories(&self) -> Vec<PathBuf> {
+        self.compiled_rules
             .iter()
-            .map(|(pattern, destination)| (pattern.clone(), destination.clone()))
+            .filter(|rule| rule.action.has_destination())
+            .map(|rule| {
+                self.destination_directory
+                    .join(rule.destination.static_prefix())
+            })
             .collect()
     }
 
-    pub fn add_dynamic_rule(&mut self, pattern: &str, destination: &str) {
-        match Regex::new(pattern) {
-            Ok(regex) => {
-                self.rules
-                    .insert(pattern.to_string(), destination.to_string());
-                self.compiled_rules.push((regex, destination.to_string()));
-                self.store_configurations();
+    /// Returns the rules in evaluation order
+    pub fn view_rules(&self) -> Vec<Rule> {
+        evaluation_order(&self.rules).into_iter().cloned().collect()
+    }
+
+    /// Returns the configurations file of every layer, in increasing precedence, along
+    /// with whether it was present
+    pub fn get_layers(&self) -> &[(Layer, PathBuf, bool)] {
+        &self.layers
+    }
+
+    /// The settings in effect, i.e. everything but the rules, along with the directories
+    pub fn effective_settings(&self) -> Value {
+        let mut settings = serde_json::to_value(self).unwrap_or_default();
+        if let Value::Object(settings) = &mut settings {
+            settings.shift_remove("rules");
+            settings.shift_remove("version");
+            settings.shift_remove("profiles");
+            settings.insert(
+                "source".to_string(),
+                Value::String(self.working_directory.display().to_string()),
+            );
+            settings.insert(
+                "destination".to_string(),
+                Value::String(self.destination_directory.display().to_string()),
+            );
+        }
+        settings
+    }
+
+    /// Stores the working and destination directories given on the command line in the
+    /// user configurations, or in the selected profile, so that they do not have to be
+    /// given again. Returns the stored directories.
+    pub fn save_directories(&mut self) -> Result<Vec<(&'static str, String)>, String> {
+        let mut saved = vec![];
+        for (key, given, directory) in [
+            ("source", self.directories_given.0, &self.working_directory),
+            (
+                "destination",
+                self.directories_given.1,
+                &self.destination_directory,
+            ),
+        ] {
+            if !given {
+                continue;
             }
-            Err(err) => {
-                error!(
-                    "Failed to compile the regex {} for {}: {}",
-                    pattern, destination, err
-                );
+            let directory = path::absolute(directory).map_err(|err| {
+                format!(
+                    "Unable to resolve the directory {}: {}",
+                    directory.display(),
+                    err
+                )
+            })?;
+            saved.push((key, contract_path(&normalize_path(&directory))));
+        }
+        if saved.is_empty() {
+            return Err("No directories to save, give them with -s and -d".to_string());
+        }
+
+        let mut config = Value::Object(std::mem::take(&mut self.user_config));
+        for (key, directory) in &saved {
+            let directory = Value::String(directory.clone());
+            match &self.profile {
+                Some(profile) => config["profiles"][profile][*key] = directory,
+                None => config[*key] = directory,
             }
         }
+        if let Value::Object(config) = config {
+            self.user_config = config;
+        }
+        self.write_configurations()?;
+        Ok(saved)
     }
 
-    pub fn delete_dynamic_rule(&mut self, pattern: &str) {
-        match Regex::new(pattern) {
-            Ok(regex) => {
-                self.rules.retain(|r, _| r != pattern);
-                self.compiled_rules
-                    .retain(|(r, _)| r.as_str() != regex.as_str());
-                self.store_configurations();
-            }
-            Err(err) => {
-                error!("Failed to find the regex {}: {}", pattern, err);
+    /// Adds a rule to the user configurations, or changes the destination of the user
+    /// rule with the same regex or glob
+    pub fn add_dynamic_rule(&mut self, new_rule: Rule) -> Result<(), RuleError> {
+        compile_rule(&new_rule).map_err(|err| {
+            RuleError::Invalid(format!(
+                "Failed to add the rule {} -> {}: {}",
+                new_rule.pattern(),
+                new_rule.destination,
+                err
+            ))
+        })?;
+
+        match self.rules.iter_mut().find(|rule| {
+            rule.layer == Layer::User && rule.regex == new_rule.regex && rule.glob == new_rule.glob
+        }) {
+            Some(rule) => rule.destination = new_rule.destination,
+            None => {
+                // The user rules come before the system rules
+                let index = self
+                    .rules
+                    .iter()
+                    .position(|rule| rule.layer == Layer::System)
+                    .unwrap_or(self.rules.len());
+                self.rules.insert(index, new_rule);
             }
         }
+        self.compile_regexes().map_err(RuleError::Failed)?;
+        self.write_configurations().map_err(RuleError::Failed)
     }
 
-    fn compile_regexes(&mut self) {
-        self.compiled_rules = self
-            .rules
-            .iter()
-            .filter_map(|(pattern, destination)| {
-                // Regex::new(r.0).ok().map(|regex| (regex, r.1.clone())
-                match Regex::new(pattern) {
-                    Ok(regex) => {
-                        debug!("Compiled regex: {} -> {}", pattern, destination);
-                        Some((regex, destination.clone()))
-                    }
-                    Err(err) => {
-                        error!(
-                            "Failed to compile the regex {} for {}: {}",
-                            pattern, destination, err
-                        );
-                        None
-                    }
-                }
+    /// Deletes the user rules with the given regex or glob
+    pub fn delete_dynamic_rule(&mut self, pattern: &str) -> Result<(), RuleError> {
+        let count = self.rules.len();
+        self.rules.retain(|rule| {
+            rule.layer != Layer::User || (rule.regex != pattern && rule.glob != pattern)
+        });
+        if self.rules.len() == count {
+            return Err(RuleError::Failed(
+                match self
+                    .rules
+                    .iter()
+                    .find(|rule| rule.regex == pattern || rule.glob == pattern)
+                {
+                    Some(rule) => format!(
+                        "The rule {} comes from the {} configurations and cannot be deleted here",
+                        pattern, rule.layer
+                    ),
+                    None => format!("Failed to find the rule {}", pattern),
+                },
+            ));
+        }
+
+        self.compile_regexes().map_err(RuleError::Failed)?;
+        self.write_configurations().map_err(RuleError::Failed)
+    }
+
+    /// Compiles the rules in evaluation order, fails on the first rule which cannot be compiled
+    fn compile_regexes(&mut self) -> Result<(), String> {
+        self.compiled_rules = evaluation_order(&self.rules)
+            .into_iter()
+            .map(|rule| {
+                let compiled_rule = compile_rule(rule).map_err(|err| {
+                    format!(
+                        "Invalid rule {} -> {} in the {} configurations: {}",
+                        rule.pattern(),
+                        rule.describe_action(),
+                        rule.layer,
+                        err
+                    )
+                })?;
+                debug!("Compiled rule: {} -> {}", rule.pattern(), rule.destination);
+                Ok(compiled_rule)
             })
-            .collect();
+            .collect::<Result<_, String>>()?;
+        Ok(())
     }
 
-    pub fn retrieve_destination_directory(&self, file_name: &str) -> Option<PathBuf> {
-        for (regex_str, destination) in &self.compiled_rules {
-            if regex_str.is_match(file_name) {
-                return Some(
-                    Path::new(&self.destination_directory)
-                        .join(destination.clone())
-                        .to_path_buf(),
-                );
+    /// Finds the first matching rule for the file and renders its destination directory,
+    /// `relative_path` is the path of the file relative to the working directory.
+    pub fn retrieve_destination_directory(
+        &self,
+        file: &Path,
+        relative_path: &Path,
+    ) -> Option<RuleMatch> {
+        let file_name = file.file_name()?.to_str()?;
+        let metadata = fs::metadata(file).ok();
+        // The content is only sniffed once a rule asks for it
+        let mut mime_type = None;
+        for rule in &self.compiled_rules {
+            let Some(captures) = rule.regex.captures(file_name) else {
+                continue;
+            };
+
+            if rule.checks_mime() {
+                let detected = *mime_type.get_or_insert_with(|| mime::sniff(file));
+                if !mime_matches(detected, &rule.mime, &rule.mime_prefix) {
+                    continue;
+                }
+            }
+
+            if rule.conditions.matches(relative_path, metadata.as_ref()) {
+                return self.rule_match(rule, &captures, file_name, metadata.as_ref());
             }
         }
 
         None
     }
 
-    pub fn load_configurations(&mut self, config_file: PathBuf) {
+    /// Evaluates every rule for the file, unlike `retrieve_destination_directory` which
+    /// stops at the first matching rule. The file does not need to exist, conditions on
+    /// the metadata and the content never hold then.
+    pub fn explain(&self, file: &Path, relative_path: &Path) -> Explanation {
+        let metadata = fs::metadata(file).ok();
+        // The content is only sniffed if any rule asks for it
+        let mime_type = if self.compiled_rules.iter().any(CompiledRule::checks_mime) {
+            mime::sniff(file)
+        } else {
+            None
+        };
+        self.evaluate_rules(relative_path, metadata.as_ref(), mime_type)
+    }
+
+    /// Evaluates every rule for a file name alone, as if the file had no metadata and
+    /// no recognisable content. `relative_path` ends with the file name.
+    pub fn explain_name(&self, relative_path: &Path) -> Explanation {
+        self.evaluate_rules(relative_path, None, None)
+    }
+
+    fn evaluate_rules(
+        &self,
+        relative_path: &Path,
+        metadata: Option<&Metadata>,
+        mime_type: Option<&'static str>,
+    ) -> Explanation {
+        let file_name = relative_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        let mut explanation = Explanation {
+            mime_type,
+            ..Default::default()
+        };
+        for rule in &self.compiled_rules {
+            let captures = rule.regex.captures(file_name);
+            let evaluation = RuleEvaluation {
+                description: rule.description.clone(),
+                name_matched: captures.is_some(),
+                mime_matched: rule
+                    .checks_mime()
+                    .then(|| mime_matches(mime_type, &rule.mime, &rule.mime_prefix)),
+                conditions: rule.conditions.evaluate(relative_path, metadata),
+            };
+
+            if explanation.winner.is_none()
+                && evaluation.matched()
+                && let Some(captures) = &captures
+                && let Some(rule_match) = self.rule_match(rule, captures, file_name, metadata)
+            {
+                explanation.winner = Some(explanation.evaluations.len());
+                explanation.rule_match = Some(rule_match);
+            }
+            explanation.evaluations.push(evaluation);
+        }
+
+        explanation
+    }
+
+    /// Renders the destination directory of a file matched by the rule. The rendered
+    /// directory is normalised and has to stay inside of the destination directory,
+    /// unless the rule allows otherwise.
+    fn rule_match(
+        &self,
+        rule: &CompiledRule,
+        captures: &Captures,
+        file_name: &str,
+        metadata: Option<&Metadata>,
+    ) -> Option<RuleMatch> {
+        let root = normalize_path(
+            &path::absolute(&self.destination_directory)
+                .unwrap_or_else(|_| self.destination_directory.clone()),
+        );
+        let rendered = rule.destination.render(Some(captures), file_name, metadata);
+        let destination = normalize_path(&root.join(&rendered));
+        if rule.action.has_destination() && !rule.allow_absolute && !destination.starts_with(&root)
+        {
+            error!(
+                "Rejected the destination {} of file {} as it leaves the destination directory {} !",
+                destination.display(),
+                file_name,
+                root.display()
+            );
+            return None;
+        }
+
+        Some(RuleMatch {
+            destination,
+            action: rule.action,
+            on_conflict: rule.on_conflict.unwrap_or(self.on_conflict),
+            allow_absolute: rule.allow_absolute,
+        })
+    }
+
+    /// Loads the configurations of every layer: the system configurations, the user
+    /// configurations file and the `.fsorg.json` in the working directory. The settings
+    /// of a higher layer override those of the lower ones, and its rules are evaluated
+    /// first. A missing or empty user configurations file is filled with the default
+    /// configurations, unless the system provides them. A file which cannot be parsed is
+    /// left untouched and reported, so that hand-edited rules are never replaced.
+    pub fn load_configurations(&mut self, config_file: PathBuf) -> Result<(), String> {
         self.configuration_file = config_file;
-        match File::open(&self.configuration_file) {
-            Ok(fp) => {
-                match serde_json::from_reader::<BufReader<File>, Configurations>(BufReader::new(fp))
-                {
-                    Ok(configurations) => {
-                        // Move the file mapper to our file_mapper
-                        self.rules = configurations.rules;
-                    }
-                    Err(err) => {
-                        error!(
-                            "Error occurred while reading from configurations file:{} !",
-                            err
-                        );
-                        self.seed_configurations();
-                        self.store_configurations();
-                    }
+        let system = LayerFile::read(
+            Layer::System,
+            &find_config_file(Path::new(SYSTEM_CONFIG_DIRECTORY), CONFIG_FILE_STEM),
+        )?;
+        let user = LayerFile::read(Layer::User, &self.configuration_file)?;
+        // The source directory decides which directory configurations apply, so it is
+        // only taken from the system and user configurations
+        if !self.directories_given.0 {
+            let base = merge_settings(&[&system, &user]);
+            let source = match &self.profile {
+                Some(name) => select_profile(&base, name)?.source,
+                None => None,
+            }
+            .or_else(|| base.get("source").and_then(Value::as_str).map(String::from));
+            if let Some(source) = source {
+                self.working_directory = expand_path(&source)
+                    .map_err(|err| format!("Invalid source directory: {}", err))?;
+                self.source_resolved = true;
+            }
+        }
+        let directory_file = find_config_file(&self.working_directory, DIRECTORY_CONFIG_STEM);
+        // The user configurations may live in the working directory itself
+        let directory = if same_file(&directory_file, &self.configuration_file) {
+            LayerFile {
+                layer: Layer::Directory,
+                path: directory_file,
+                config: None,
+                migrated_from: None,
+            }
+        } else {
+            LayerFile::read(Layer::Directory, &directory_file)?
+        };
+        directory.check_directory_settings()?;
+
+        let mut merged = merge_settings(&[&system, &user, &directory]);
+        let mut destination = None;
+        if let Some(name) = &self.profile {
+            let profile = select_profile(&merged, name)?;
+            merge_into(&mut merged, &profile.settings);
+            destination = profile.destination;
+            // A profile without rules of its own uses the rules of the configurations
+            if profile.rules.is_some() {
+                self.rules_profile = Some(name.clone());
+            }
+        }
+        let settings: Configurations = serde_json::from_value(merged)
+            .map_err(|err| format!("Unable to merge the configurations: {}", err))?;
+        self.scan = settings.scan;
+        self.on_conflict = settings.on_conflict;
+        self.safeguards = settings.safeguards;
+        self.ignore = settings.ignore;
+        self.profiles = settings.profiles;
+        // The directory configurations cannot grant themselves more powers
+        self.directory_rules_may_delete = merge_settings(&[&system, &user])
+            .get("directory_rules_may_delete")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        self.source = settings.source;
+        self.destination = settings.destination;
+        if !self.directories_given.1
+            && let Some(destination) = destination.or_else(|| self.destination.clone())
+        {
+            self.destination_directory = expand_path(&destination)
+                .map_err(|err| format!("Invalid destination directory: {}", err))?;
+        }
+
+        self.rules = vec![];
+        for layer in [&directory, &user, &system] {
+            let Some(rules) = layer.rules(self.rules_profile.as_deref()) else {
+                continue;
+            };
+            let rules: Vec<Rule> = serde_json::from_value(rules)
+                .map_err(|err| format!("Invalid rules in {}: {}", layer.path.display(), err))?;
+            if layer.layer == Layer::Directory {
+                for rule in &rules {
+                    self.check_directory_rule(rule, &layer.path)?;
                 }
             }
-            Err(_) => {
-                self.seed_configurations();
-                self.store_configurations();
+            self.rules.extend(rules.into_iter().map(|rule| Rule {
+                layer: layer.layer,
+                ..rule
+            }));
+        }
+        self.migrated_from = user.migrated_from;
+
+        let user_present = user.config.is_some();
+        match user.config {
+            Some(config) => self.user_config = config,
+            None => {
+                if self.configuration_file.exists() {
+                    back_up(&self.configuration_file)?;
+                }
+                // Only the defaults are written, the settings of the other layers stay there
+                if system.config.is_none() {
+                    let mut defaults = Configurations::new();
+                    defaults.seed_configurations();
+                    if let Ok(Value::Object(config)) = serde_json::to_value(&defaults) {
+                        self.user_config = config;
+                    }
+                    self.rules.append(&mut defaults.rules);
+                    self.store_configurations();
+                }
             }
         }
+        self.layers = vec![
+            (Layer::System, system.path, system.config.is_some()),
+            // The seeded user configurations count as present once written
+            (
+                Layer::User,
+                user.path,
+                user_present || self.configuration_file.exists(),
+            ),
+            (Layer::Directory, directory.path, directory.config.is_some()),
+        ];
 
-        self.compile_regexes();
+        self.compile_regexes()?;
+        if let Err(err) = Safeguards::new(&self.safeguards) {
+            error!("{}, using the default safeguards !", err);
+            self.safeguards = SafeguardOptions::default();
+        }
+        Ok(())
+    }
+
+    /// Fails if a rule of the directory configurations goes beyond what it may do. The
+    /// working directory may hold files from untrusted sources, so its rules can neither
+    /// leave the destination directory nor replace existing files, and can only delete
+    /// files when the user allows that.
+    fn check_directory_rule(&self, rule: &Rule, path: &Path) -> Result<(), String> {
+        let reason = if rule.allow_absolute {
+            "allow_absolute is not permitted in directory configurations"
+        } else if rule.action == ActionKind::Delete && !self.directory_rules_may_delete {
+            "deleting files needs directory_rules_may_delete in the user configurations"
+        } else if matches!(
+            rule.on_conflict,
+            Some(
+                ConflictStrategy::Overwrite
+                    | ConflictStrategy::KeepNewer
+                    | ConflictStrategy::KeepLarger
+            )
+        ) {
+            "replacing existing files is not permitted in directory configurations"
+        } else {
+            return Ok(());
+        };
+        Err(format!(
+            "Invalid rule {} -> {} in {}: {}",
+            rule.pattern(),
+            rule.describe_action(),
+            path.display(),
+            reason
+        ))
+    }
+
+    /// Rewrites a user configurations file of an older schema version in the latest
+    /// version, after keeping a backup of it. Returns the version it was migrated from, if any.
+    pub fn migrate_configurations(&mut self) -> Result<Option<u32>, String> {
+        let Some(version) = self.migrated_from else {
+            return Ok(None);
+        };
+
+        self.write_configurations()?;
+        Ok(Some(version))
     }
 
     fn seed_configurations(&mut self) {
         warn!("Loading default configurations !");
-        self.rules = HashMap::from([
-            (
-                r"(?i)^.*\.(jpg|jpeg|png|gif|bmp|webp|tiff?)$".to_string(),
-                "Images".to_string(),
+        self.rules = vec![
+            Rule::new(r"(?i)^.*\.(jpg|jpeg|png|gif|bmp|webp|tiff?)$", "Images"),
+            Rule::new(
+                r"(?i)^.*\.(pdf|docx?|xlsx?|pptx?|odt|ods|txt|rtf|csv|md)$",
+                "Documents",
             ),
-            (
-                r"(?i)^.*\.(pdf|docx?|xlsx?|pptx?|odt|ods|txt|rtf|csv|md)$".to_string(),
-                "Documents".to_string(),
+            Rule::new(r"(?i)^.*\.(mp4|mkv|flv|avi|mov)$", "Videos"),
+            Rule::new(r"(?i)^.*\.(mp3|wav|ogg|flac)$", "Music"),
+            Rule::new(r"(?i)^.*\.(zip|rar|7z|tar\.gz|tar\.bz2)$", "Archives"),
+            Rule::new(r"(?i)^.*\.(exe|msi|deb|rpm|sh|bat)$", "Installers"),
+            Rule::new(r"(?i)^.*\.(rs|cpp|c|h|hpp|py|java|go|rb|cs|swift)$", "Code"),
+            // Files with a missing or unknown extension are matched by their content
+            Rule::fallback_by_mime(&["image/"], &[], "Images"),
+            Rule::fallback_by_mime(&["video/"], &[], "Videos"),
+            Rule::fallback_by_mime(&["audio/"], &[], "Music"),
+            Rule::fallback_by_mime(
+                &[],
+                &["application/pdf", "application/epub+zip"],
+                "Documents",
             ),
-            (
-                "(?i)^.*\\.(mp4|mkv|flv|avi|mov)$".to_string(),
-                "Videos".to_string(),
+            Rule::fallback_by_mime(
+                &[],
+                &[
+                    "application/zip",
+                    "application/gzip",
+                    "application/x-bzip2",
+                    "application/x-xz",
+                    "application/x-7z-compressed",
+                    "application/vnd.rar",
+                    "application/zstd",
+                    "application/x-tar",
+                ],
+                "Archives",
             ),
-            (
-                "(?i)^.*\\.(mp3|wav|ogg|flac)$".to_string(),
-                "Music".to_string(),
+            Rule::fallback_by_mime(
+                &[],
+                &[
+                    "application/x-executable",
+                    "application/vnd.microsoft.portable-executable",
+                    "application/vnd.debian.binary-package",
+                    "application/x-rpm",
+                ],
+                "Installers",
             ),
-            (
-                "(?i)^.*\\.(zip|rar|7z|tar\\.gz|tar\\.bz2)$".to_string(),
-                "Archives".to_string(),
-            ),
-            (
-                "(?i)^.*\\.(exe|msi|deb|rpm|sh|bat)$".to_string(),
-                "Installers".to_string(),
-            ),
-            (
-                "(?i)^.*\\.(rs|cpp|c|h|hpp|py|java|go|rb|cs|swift)$".to_string(),
-                "Code".to_string(),
-            ),
-        ]);
+        ];
         debug!("Default configurations loaded: {:?}", self.rules);
     }
 
-    fn store_configurations(&self) {
+    fn store_configurations(&mut self) {
+        if let Err(err) = self.write_configurations() {
+            error!("{} !", err);
+        }
+    }
+
+    /// Writes the user configurations to the file in the format of its extension, a file
+    /// of an older schema version is backed up first.
+    fn write_configurations(&mut self) -> Result<(), String> {
         info!("Writing configurations to file !");
-        match File::create(&self.configuration_file) {
-            Ok(mut fp) => match serde_json::to_string_pretty(&self) {
-                Ok(config) => match fp.write_all(config.as_bytes()) {
-                    Ok(()) => {}
-                    Err(err) => error!("Unable to write configurations to file: {} !", err),
-                },
-                Err(err) => error!("Unable to serialize the configurations: {} !", err),
-            },
-            Err(err) => error!(
-                "Unable to open file: {} for writing configurations: {} !",
-                self.configuration_file.display(),
+        if self.migrated_from.is_some() {
+            back_up(&self.configuration_file)?;
+            self.migrated_from = None;
+        }
+
+        write_config_file(&self.configuration_file, &self.user_document()?)
+    }
+
+    /// Writes the user configurations to another file, in the format of its extension.
+    /// The written file is read back to make sure nothing was lost in the conversion.
+    pub fn convert_configurations(&self, output: &Path) -> Result<(), String> {
+        if output.exists() {
+            return Err(format!("The file {} already exists", output.display()));
+        }
+
+        let document = self.user_document()?;
+        write_config_file(output, &document)?;
+        let converted = LayerFile::read(Layer::User, output)?
+            .config
+            .map(Value::Object)
+            .unwrap_or_default();
+        if without_nulls(&converted) != without_nulls(&document) {
+            let _ = fs::remove_file(output);
+            return Err(format!(
+                "The configurations cannot be converted to {} without losing information",
+                output.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// The content of the user configurations file: the settings of the file as they
+    /// were, so that neither other layers nor the command line options leak into it,
+    /// along with the user rules.
+    fn user_document(&self) -> Result<Value, String> {
+        let rules: Vec<&Rule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.layer == Layer::User)
+            .collect();
+        let rules = serde_json::to_value(rules)
+            .map_err(|err| format!("Unable to serialize the rules: {}", err))?;
+        let mut config = Value::Object(self.user_config.clone());
+        match &self.rules_profile {
+            Some(profile) => {
+                // The profile only gets rules in the user configurations when it has some
+                let existing = config
+                    .get("profiles")
+                    .and_then(|profiles| profiles.get(profile))
+                    .and_then(|profile| profile.get("rules"));
+                if existing.is_some() || rules.as_array().is_some_and(|rules| !rules.is_empty()) {
+                    config["profiles"][profile]["rules"] = rules;
+                }
+            }
+            None => config["rules"] = rules,
+        }
+        config["version"] = Value::String(CONFIG_VERSION.to_string());
+        Ok(config)
+    }
+}
+
+/// Writes a configurations file in the format of its extension, atomically
+fn write_config_file(path: &Path, config: &Value) -> Result<(), String> {
+    let config = ConfigFormat::of(path)
+        .serialize(config)
+        .map_err(|err| format!("Unable to serialize the configurations: {}", err))?;
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent).map_err(|err| {
+            format!(
+                "Unable to create the directory {}: {}",
+                parent.display(),
                 err
+            )
+        })?;
+    }
+    write_atomically(path, config.as_bytes()).map_err(|err| {
+        format!(
+            "Unable to write configurations to file {}: {}",
+            path.display(),
+            err
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A temporary source directory holding the given directory configurations, along
+    /// with a user configurations file next to it. Removed when dropped.
+    struct Fixture {
+        root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str, user_config: &str, directory_config: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "fsorg-configurations-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("source")).unwrap();
+            fs::write(root.join("config.json"), user_config).unwrap();
+            fs::write(root.join("source").join(".fsorg.json"), directory_config).unwrap();
+            Fixture { root }
+        }
+
+        fn load(&self) -> Result<Configurations, String> {
+            let mut configurations = Configurations::new();
+            configurations.set_working_directory(self.root.join("source"));
+            configurations.set_destination_directory(self.root.join("destination"));
+            configurations
+                .load_configurations(self.root.join("config.json"))
+                .map(|()| configurations)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    const USER_CONFIG: &str = r#"{ "version": "2", "rules": [] }"#;
+
+    #[test]
+    fn directory_configurations_cannot_change_the_destination_or_conflict_strategy() {
+        let fixture = Fixture::new(
+            "hostile-settings",
+            USER_CONFIG,
+            r#"{ "destination": "/tmp/evil", "on_conflict": "overwrite" }"#,
+        );
+        let err = fixture.load().err().unwrap();
+        assert!(
+            err.contains("cannot set destination, on_conflict"),
+            "{}",
+            err
+        );
+
+        for settings in [
+            r#"{ "safeguards": { "grace_period": "0s" } }"#,
+            r#"{ "profiles": { "x": { "destination": "/tmp/evil" } } }"#,
+            r#"{ "directory_rules_may_delete": true }"#,
+        ] {
+            let fixture = Fixture::new("hostile-other", USER_CONFIG, settings);
+            assert!(fixture.load().is_err(), "accepted {}", settings);
+        }
+    }
+
+    #[test]
+    fn directory_rules_cannot_escape_replace_or_delete() {
+        for rule in [
+            r#"{ "glob": "*.txt", "destination": "/tmp/evil", "allow_absolute": true }"#,
+            r#"{ "glob": "*.txt", "destination": "Text", "on_conflict": "overwrite" }"#,
+            r#"{ "glob": "*.txt", "destination": "Text", "on_conflict": "keep_larger" }"#,
+            r#"{ "glob": "*.txt", "action": "delete" }"#,
+        ] {
+            let fixture = Fixture::new(
+                "hostile-rule",
+                USER_CONFIG,
+                &format!(r#"{{ "rules": [{}] }}"#, rule),
+            );
+            assert!(fixture.load().is_err(), "accepted {}", rule);
+        }
+    }
+
+    #[test]
+    fn directory_rules_may_delete_when_the_user_allows_it() {
+        let fixture = Fixture::new(
+            "allowed-delete",
+            r#"{ "version": "2", "rules": [], "directory_rules_may_delete": true }"#,
+            r#"{ "rules": [{ "glob": "*.tmp", "action": "delete" }] }"#,
+        );
+        let configurations = fixture.load().unwrap();
+        let rules = configurations.view_rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].layer, Layer::Directory);
+    }
+
+    #[test]
+    fn directory_configurations_keep_their_allowed_settings() {
+        let fixture = Fixture::new(
+            "allowed-settings",
+            r#"{ "version": "2", "rules": [], "on_conflict": "skip" }"#,
+            r#"{ "version": "2", "scan": { "max_depth": 1 }, "ignore": ["*.part"], "rules": [{ "glob": "*.md", "destination": "Notes" }] }"#,
+        );
+        let configurations = fixture.load().unwrap();
+        assert_eq!(configurations.on_conflict, ConflictStrategy::Skip);
+        assert_eq!(configurations.scan.max_depth, Some(1));
+        assert_eq!(
+            configurations.get_destination_directory(),
+            &fixture.root.join("destination")
+        );
+    }
+
+    #[test]
+    fn invalid_rules_fail_loading() {
+        for (rule, error) in [
+            (
+                r#"{ "regex": ".*\\.pdf$", "destination": "Docs/{year}" }"#,
+                "unknown placeholder {year}",
             ),
+            (
+                r#"{ "regex": "(unclosed", "destination": "Docs" }"#,
+                "Invalid rule (unclosed -> Docs",
+            ),
+        ] {
+            let fixture = Fixture::new(
+                "invalid-rule",
+                &format!(r#"{{ "version": "2", "rules": [{}] }}"#, rule),
+                "{}",
+            );
+            let err = fixture.load().err().unwrap();
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+
+    #[test]
+    fn destinations_escaping_the_root() {
+        for destination in ["Docs", "Docs/../Text", "./Docs", "Docs/{1}/..", ""] {
+            assert_eq!(escapes_root(destination), None, "{}", destination);
         }
+        for (destination, reason) in [
+            ("/etc", "it is an absolute path"),
+            ("../../etc", "it climbs above it via .."),
+            ("Docs/../../etc", "it climbs above it via .."),
+            ("./..", "it climbs above it via .."),
+        ] {
+            assert_eq!(escapes_root(destination), Some(reason), "{}", destination);
+        }
+    }
+
+    #[test]
+    fn rendered_destinations_stay_inside_the_destination_directory() {
+        let fixture = Fixture::new(
+            "rendered-destination",
+            r#"{ "version": "2", "rules": [
+                { "regex": "^(?<name>.*)\\.txt$", "destination": "Text/{name}" },
+                { "regex": "^(.*)\\.md$", "destination": "Notes/{1}/.." }
+            ] }"#,
+            "{}",
+        );
+        let configurations = fixture.load().unwrap();
+        let destination = |file_name: &str| {
+            configurations
+                .retrieve_destination_directory(
+                    &fixture.root.join("source").join(file_name),
+                    Path::new(file_name),
+                )
+                .map(|rule_match| rule_match.destination)
+        };
+        let root = fixture.root.join("destination");
+
+        // Captured values cannot add path components
+        assert_eq!(destination("...txt"), Some(root.join("Text/_")));
+        assert_eq!(destination("a.txt"), Some(root.join("Text/a")));
+        // A literal .. after a placeholder is resolved once rendered
+        assert_eq!(destination("a.md"), Some(root.join("Notes")));
+        assert_eq!(destination("...md"), Some(root.join("Notes")));
     }
 }
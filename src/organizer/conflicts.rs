@@ -0,0 +1,382 @@
+use super::hash_file;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What to do when the destination of a file is already taken
+pub enum ConflictStrategy {
+    /// Leave the source file where it is
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Place the file next to the existing one with a numeric suffix, e.g. `photo (1).png`
+    #[default]
+    Rename,
+    /// Replace the existing file only if the source file was modified more recently
+    KeepNewer,
+    /// Replace the existing file only if the source file is larger
+    KeepLarger,
+    /// Drop the source file if it has the same content as the existing file, rename otherwise
+    Deduplicate,
+}
+
+impl fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConflictStrategy::Skip => "skip",
+            ConflictStrategy::Overwrite => "overwrite",
+            ConflictStrategy::Rename => "rename",
+            ConflictStrategy::KeepNewer => "keep_newer",
+            ConflictStrategy::KeepLarger => "keep_larger",
+            ConflictStrategy::Deduplicate => "deduplicate",
+        })
+    }
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().replace('-', "_").as_str() {
+            "skip" => Ok(ConflictStrategy::Skip),
+            "overwrite" => Ok(ConflictStrategy::Overwrite),
+            "rename" => Ok(ConflictStrategy::Rename),
+            "keep_newer" => Ok(ConflictStrategy::KeepNewer),
+            "keep_larger" => Ok(ConflictStrategy::KeepLarger),
+            "deduplicate" => Ok(ConflictStrategy::Deduplicate),
+            _ => Err(format!("Unknown conflict strategy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// The outcome of applying a conflict strategy to a file
+pub enum Decision {
+    /// The destination is free
+    #[default]
+    NoConflict,
+    /// The source file is left in place
+    Skip,
+    /// The existing file gets replaced
+    Overwrite,
+    /// The file is placed under a new name
+    Rename,
+    /// The source file is an exact duplicate of the existing file and gets removed
+    DropDuplicate,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Decision::NoConflict => "no_conflict",
+            Decision::Skip => "skip",
+            Decision::Overwrite => "overwrite",
+            Decision::Rename => "rename",
+            Decision::DropDuplicate => "drop_duplicate",
+        })
+    }
+}
+
+impl FromStr for Decision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "no_conflict" => Ok(Decision::NoConflict),
+            "skip" => Ok(Decision::Skip),
+            "overwrite" => Ok(Decision::Overwrite),
+            "rename" => Ok(Decision::Rename),
+            "drop_duplicate" => Ok(Decision::DropDuplicate),
+            _ => Err(format!("Unknown conflict decision: {}", s)),
+        }
+    }
+}
+
+/// Applies the strategy to a source file whose destination is taken by `existing`.
+/// `existing` is usually the destination itself, but it can also be another source file
+/// which is already planned to be placed at the destination.
+/// `is_taken` tells whether a candidate path is unavailable, it is used while renaming.
+///
+/// Returns the decision along with the final destination of the file.
+pub fn resolve(
+    source: &Path,
+    destination: &Path,
+    existing: &Path,
+    strategy: ConflictStrategy,
+    is_taken: impl Fn(&Path) -> bool,
+) -> (Decision, PathBuf) {
+    let replace_if = |condition: bool| {
+        if condition {
+            (Decision::Overwrite, destination.to_path_buf())
+        } else {
+            (Decision::Skip, destination.to_path_buf())
+        }
+    };
+
+    match strategy {
+        ConflictStrategy::Skip => (Decision::Skip, destination.to_path_buf()),
+        ConflictStrategy::Overwrite => (Decision::Overwrite, destination.to_path_buf()),
+        ConflictStrategy::Rename => (Decision::Rename, unique_destination(destination, is_taken)),
+        ConflictStrategy::KeepNewer => {
+            let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+            replace_if(matches!(
+                (modified(source), modified(existing)),
+                (Some(source), Some(existing)) if source > existing
+            ))
+        }
+        ConflictStrategy::KeepLarger => {
+            let size = |path: &Path| fs::metadata(path).map(|m| m.len()).ok();
+            replace_if(matches!(
+                (size(source), size(existing)),
+                (Some(source), Some(existing)) if source > existing
+            ))
+        }
+        ConflictStrategy::Deduplicate => {
+            if is_duplicate(source, existing) {
+                (Decision::DropDuplicate, destination.to_path_buf())
+            } else {
+                (Decision::Rename, unique_destination(destination, is_taken))
+            }
+        }
+    }
+}
+
+/// Compares the content of two files by their hashes
+fn is_duplicate(source: &Path, existing: &Path) -> bool {
+    let same_size = match (fs::metadata(source), fs::metadata(existing)) {
+        (Ok(source), Ok(existing)) => source.len() == existing.len(),
+        _ => false,
+    };
+    if !same_size {
+        return false;
+    }
+
+    match (hash_file(source), hash_file(existing)) {
+        (Ok(source), Ok(existing)) => source == existing,
+        (Err(err), _) | (_, Err(err)) => {
+            error!(
+                "Unable to compare {} with {}: {}",
+                source.display(),
+                existing.display(),
+                err
+            );
+            false
+        }
+    }
+}
+
+/// Finds the first free name of the form `name (N).ext` next to the destination
+fn unique_destination(destination: &Path, is_taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let file_name = destination
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (stem, extension) = split_extension(&file_name);
+
+    let mut counter = 1;
+    loop {
+        let candidate = destination.with_file_name(format!("{} ({}){}", stem, counter, extension));
+        if !is_taken(&candidate) {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Splits a file name into its stem and its extension including the dot. The extension
+/// starts at the first dot, so that `archive.tar.gz` becomes `archive (1).tar.gz`, a
+/// leading dot of hidden files is not considered as an extension.
+pub fn split_extension(file_name: &str) -> (&str, &str) {
+    match file_name.char_indices().skip(1).find(|(_, c)| *c == '.') {
+        Some((index, _)) => file_name.split_at(index),
+        None => (file_name, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// A temporary directory, removed when dropped
+    struct Directory(PathBuf);
+
+    impl Directory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "fsorg-conflicts-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Directory(path)
+        }
+
+        /// Writes a file modified the given seconds after the unix epoch
+        fn file(&self, name: &str, content: &str, modified: u64) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, content).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(modified))
+                .unwrap();
+            path
+        }
+    }
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn resolve_with(
+        source: &Path,
+        existing: &Path,
+        strategy: ConflictStrategy,
+    ) -> (Decision, PathBuf) {
+        resolve(source, existing, existing, strategy, |candidate| {
+            candidate.exists()
+        })
+    }
+
+    #[test]
+    fn skips_or_overwrites() {
+        let directory = Directory::new("skip-overwrite");
+        let source = directory.file("source.txt", "new", 2000);
+        let existing = directory.file("existing.txt", "old", 1000);
+
+        assert_eq!(
+            resolve_with(&source, &existing, ConflictStrategy::Skip),
+            (Decision::Skip, existing.clone())
+        );
+        assert_eq!(
+            resolve_with(&source, &existing, ConflictStrategy::Overwrite),
+            (Decision::Overwrite, existing.clone())
+        );
+        assert_eq!(
+            resolve_with(&source, &existing, ConflictStrategy::Rename),
+            (Decision::Rename, directory.0.join("existing (1).txt"))
+        );
+    }
+
+    #[test]
+    fn keeps_the_newer_file() {
+        let directory = Directory::new("keep-newer");
+        let older = directory.file("older.txt", "a", 1000);
+        let newer = directory.file("newer.txt", "a", 2000);
+        let missing = directory.0.join("missing.txt");
+
+        assert_eq!(
+            resolve_with(&newer, &older, ConflictStrategy::KeepNewer),
+            (Decision::Overwrite, older.clone())
+        );
+        assert_eq!(
+            resolve_with(&older, &newer, ConflictStrategy::KeepNewer),
+            (Decision::Skip, newer.clone())
+        );
+        assert_eq!(
+            resolve_with(&older, &older, ConflictStrategy::KeepNewer).0,
+            Decision::Skip
+        );
+        // Nothing is replaced when a modification time is unknown
+        assert_eq!(
+            resolve_with(&missing, &older, ConflictStrategy::KeepNewer).0,
+            Decision::Skip
+        );
+    }
+
+    #[test]
+    fn keeps_the_larger_file() {
+        let directory = Directory::new("keep-larger");
+        let small = directory.file("small.txt", "a", 2000);
+        let large = directory.file("large.txt", "abc", 1000);
+
+        assert_eq!(
+            resolve_with(&large, &small, ConflictStrategy::KeepLarger),
+            (Decision::Overwrite, small.clone())
+        );
+        assert_eq!(
+            resolve_with(&small, &large, ConflictStrategy::KeepLarger),
+            (Decision::Skip, large.clone())
+        );
+        assert_eq!(
+            resolve_with(&large, &large, ConflictStrategy::KeepLarger).0,
+            Decision::Skip
+        );
+    }
+
+    #[test]
+    fn deduplicates_identical_files() {
+        let directory = Directory::new("deduplicate");
+        let existing = directory.file("photo.png", "abc", 1000);
+        let duplicate = directory.file("duplicate.png", "abc", 2000);
+        let same_size = directory.file("same-size.png", "abd", 1000);
+        let other_size = directory.file("other-size.png", "abcd", 1000);
+
+        assert!(is_duplicate(&duplicate, &existing));
+        assert!(!is_duplicate(&same_size, &existing));
+        assert!(!is_duplicate(&other_size, &existing));
+        assert!(!is_duplicate(&directory.0.join("missing.png"), &existing));
+
+        assert_eq!(
+            resolve_with(&duplicate, &existing, ConflictStrategy::Deduplicate),
+            (Decision::DropDuplicate, existing.clone())
+        );
+        for different in [same_size, other_size] {
+            assert_eq!(
+                resolve_with(&different, &existing, ConflictStrategy::Deduplicate),
+                (Decision::Rename, directory.0.join("photo (1).png"))
+            );
+        }
+    }
+
+    #[test]
+    fn suffixes_the_stem_of_unique_destinations() {
+        let free = |destination: &str| unique_destination(Path::new(destination), |_| false);
+        assert_eq!(
+            free("/d/archive.tar.gz"),
+            Path::new("/d/archive (1).tar.gz")
+        );
+        assert_eq!(free("/d/.bashrc"), Path::new("/d/.bashrc (1)"));
+        assert_eq!(free("/d/.config.json"), Path::new("/d/.config (1).json"));
+        assert_eq!(free("/d/Makefile"), Path::new("/d/Makefile (1)"));
+
+        let taken = [
+            PathBuf::from("/d/photo (1).png"),
+            PathBuf::from("/d/photo (2).png"),
+        ];
+        assert_eq!(
+            unique_destination(Path::new("/d/photo.png"), |candidate| {
+                taken[..1].iter().any(|t| t == candidate)
+            }),
+            Path::new("/d/photo (2).png")
+        );
+        assert_eq!(
+            unique_destination(Path::new("/d/photo.png"), |candidate| {
+                taken.iter().any(|t| t == candidate)
+            }),
+            Path::new("/d/photo (3).png")
+        );
+    }
+
+    #[test]
+    fn splits_extensions_at_the_first_dot() {
+        assert_eq!(split_extension("archive.tar.gz"), ("archive", ".tar.gz"));
+        assert_eq!(split_extension("photo.png"), ("photo", ".png"));
+        assert_eq!(split_extension(".bashrc"), (".bashrc", ""));
+        assert_eq!(split_extension("..."), (".", ".."));
+        assert_eq!(split_extension("Makefile"), ("Makefile", ""));
+        assert_eq!(split_extension(""), ("", ""));
+    }
+}
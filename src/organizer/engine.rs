@@ -1,13 +1,73 @@
 use super::actions::Actions;
-use super::configurations::Configurations;
+use super::config_file::{CONFIG_FILE_STEM, Layer, find_config_file};
+use super::configurations::{
+    Configurations, Explanation, Rule, RuleError, RuleMatch, ScanOptions, escapes_root,
+};
+use super::conflicts::ConflictStrategy;
+use super::ignore::IgnoreList;
+use super::lint::{self, CORPUS, LintReport};
+use super::plan::PlanHeader;
+use super::safeguards::{Deferral, SafeguardOptions, Safeguards};
+#[cfg(target_os = "linux")]
+use super::watch::{POLL_INTERVAL, PendingFiles, Watcher};
 use super::{FAIL_CONFIG_FILE, get_home_dir};
 use log::error;
+use serde_json::Value;
+use std::env;
 use std::fs;
-use std::path::Path;
-use std::path::PathBuf;
+use std::io;
+use std::path::{self, Path, PathBuf};
+use std::time::Duration;
 
+/// What a scan leaves alone, resolved once per scan
+struct ScanFilters {
+    /// Rule destinations inside the working directory, which must not be scanned
+    excluded: Vec<PathBuf>,
+
+    /// Protection of files which are possibly still being written
+    safeguards: Safeguards,
+
+    /// Patterns of the ignore file and the configurations
+    ignores: IgnoreList,
+}
+
+/// Why a file goes where it goes, see `Engine::explain`
+pub struct FileExplanation {
+    pub file: PathBuf,
+
+    /// Path of the file relative to the working directory
+    pub relative_path: PathBuf,
+
+    /// Whether the file is left alone due to an ignore pattern
+    pub ignored: bool,
+
+    /// Why the file would not be organised yet, if so
+    pub deferral: Option<Deferral>,
+
+    /// Evaluation of every rule
+    pub rules: Explanation,
+
+    /// Path where the first matching rule would place the file
+    pub destination: Option<PathBuf>,
+}
+
+/// The user configurations file, `$XDG_CONFIG_HOME/fsorg/config.json` defaulting to
+/// `~/.config/fsorg/config.json`, or a `config.toml` or `config.yaml` next to it. The
+/// `~/.fsorg.json` of older versions is kept in use as long as none of them exists.
 fn get_config_file_path() -> Option<PathBuf> {
-    get_home_dir().map(|d| d.join(".fsorg.json"))
+    let legacy = get_home_dir().map(|d| d.join(".fsorg.json"));
+    let config_file = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|d| d.is_absolute())
+        .or_else(|| get_home_dir().map(|d| d.join(".config")))
+        .map(|d| find_config_file(&d.join("fsorg"), CONFIG_FILE_STEM));
+
+    match (config_file, legacy) {
+        (Some(config_file), Some(legacy)) if !config_file.exists() && legacy.exists() => {
+            Some(legacy)
+        }
+        (config_file, legacy) => config_file.or(legacy),
+    }
 }
 
 /// Engine - The main file organiser module
@@ -17,6 +77,9 @@ pub struct Engine {
 
     /// Points to config file
     config_file: PathBuf,
+
+    /// Record the content hash of the source files in the generated actions
+    hash_sources: bool,
 }
 
 impl Engine {
@@ -28,6 +91,7 @@ impl Engine {
                 Some(home_dir) => home_dir,
                 None => PathBuf::from(FAIL_CONFIG_FILE),
             },
+            hash_sources: false,
         }
     }
 
@@ -36,6 +100,15 @@ impl Engine {
         self.configurations.set_working_directory(directory);
     }
 
+    pub fn get_working_directory(&self) -> &PathBuf {
+        self.configurations.get_working_directory()
+    }
+
+    /// The working directory, if it was given or configured rather than the current directory
+    pub fn get_source_directory(&self) -> Option<&PathBuf> {
+        self.configurations.get_source_directory()
+    }
+
     /// Changes the destination directory, where we have to place the organised files.
     pub fn change_destination_directory(&mut self, directory: PathBuf) {
         self.configurations.set_destination_directory(directory);
@@ -46,72 +119,491 @@ impl Engine {
         self.config_file = config_file;
     }
 
+    /// Selects a profile of the configurations, before they are loaded
+    pub fn select_profile(&mut self, profile: &str) {
+        self.configurations.set_profile(profile);
+    }
+
+    pub fn get_profile(&self) -> Option<&str> {
+        self.configurations.get_profile()
+    }
+
+    /// Names of the profiles of the loaded configurations
+    pub fn profile_names(&self) -> Vec<String> {
+        self.configurations.profile_names()
+    }
+
+    pub fn get_destination_directory(&self) -> &PathBuf {
+        self.configurations.get_destination_directory()
+    }
+
     /// Loads the currently set configurations into memory, and compiles the regular expressions.
-    pub fn load_configurations(&mut self) {
+    pub fn load_configurations(&mut self) -> Result<(), String> {
         self.configurations
-            .load_configurations(self.config_file.clone());
+            .load_configurations(self.config_file.clone())
+    }
+
+    /// Rewrites the configurations file in the latest schema version, if it is older.
+    /// Returns the version it was migrated from.
+    pub fn migrate_configurations(&mut self) -> Result<Option<u32>, String> {
+        self.configurations.migrate_configurations()
+    }
+
+    /// Stores the directories given on the command line in the user configurations, or in
+    /// the selected profile. Returns the stored directories.
+    pub fn save_directories(&mut self) -> Result<Vec<(&'static str, String)>, String> {
+        self.configurations.save_directories()
+    }
+
+    /// Writes the user configurations to another file, converting them to the format of
+    /// its extension: JSON, TOML or YAML
+    pub fn convert_configurations(&self, output: &Path) -> Result<(), String> {
+        self.configurations.convert_configurations(output)
     }
 
-    pub fn retrieve_rules(&self) -> Vec<(String, String)> {
+    /// The configurations file of every layer in increasing precedence, whether it was
+    /// present, and the settings in effect after the command line options
+    pub fn effective_configurations(&self) -> (&[(Layer, PathBuf, bool)], Value) {
+        (
+            self.configurations.get_layers(),
+            self.configurations.effective_settings(),
+        )
+    }
+
+    /// Retrieves the rules in the order they are evaluated
+    pub fn retrieve_rules(&self) -> Vec<Rule> {
         self.configurations.view_rules()
     }
 
-    pub fn add_rule(&mut self, pattern: &str, destination: &str) {
-        self.configurations.add_dynamic_rule(pattern, destination);
+    pub fn add_rule(&mut self, pattern: &str, destination: &str) -> Result<(), RuleError> {
+        self.configurations
+            .add_dynamic_rule(Rule::new(pattern, destination))
+    }
+
+    /// Adds a rule matching the file names with a glob, regardless of their case
+    pub fn add_glob_rule(&mut self, glob: &str, destination: &str) -> Result<(), RuleError> {
+        self.configurations
+            .add_dynamic_rule(Rule::glob(glob, destination))
+    }
+
+    pub fn delete_rule(&mut self, pattern: &str) -> Result<(), RuleError> {
+        self.configurations.delete_dynamic_rule(pattern)
+    }
+
+    /// Changes the conflict strategy for the rules which do not specify their own
+    pub fn change_conflict_strategy(&mut self, strategy: ConflictStrategy) {
+        self.configurations.set_conflict_strategy(strategy);
+    }
+
+    /// Records the content hash of every source file in the generated actions, so that
+    /// content changes are detected before executing a plan.
+    pub fn enable_source_hashes(&mut self) {
+        self.hash_sources = true;
+    }
+
+    /// Mutable access to the scanning options, used for overriding the configured values
+    pub fn scan_options_mut(&mut self) -> &mut ScanOptions {
+        self.configurations.get_scan_options_mut()
+    }
+
+    /// Mutable access to the safeguard options, used for overriding the configured values
+    pub fn safeguard_options_mut(&mut self) -> &mut SafeguardOptions {
+        self.configurations.get_safeguard_options_mut()
+    }
+
+    /// Generates actions, fails if the working directory cannot be resolved or listed
+    pub fn generate_actions(&self) -> Result<Actions, String> {
+        let working_directory = self.configurations.get_working_directory();
+        let root = fs::canonicalize(working_directory).map_err(|err| {
+            format!(
+                "Unable to resolve the working directory {}: {}",
+                working_directory.display(),
+                err
+            )
+        })?;
+        let filters = self.scan_filters(&root)?;
+
+        let mut actions = self.new_actions(&root);
+        let errors =
+            self.scan_directory(&root, Path::new(""), 0, &filters, &mut |file, relative| {
+                self.plan_file(file, relative, &filters, &mut actions);
+            })?;
+        actions.total_files_errors += errors;
+        Ok(actions)
+    }
+
+    /// Resolves what a scan of the canonical working directory `root` leaves alone
+    fn scan_filters(&self, root: &Path) -> Result<ScanFilters, String> {
+        // Skip the rule destinations, in case they are placed inside the working directory.
+        // Destinations which do not exist yet may be created while watching.
+        let excluded = self
+            .configurations
+            .rule_destination_directories()
+            .iter()
+            .filter_map(|directory| {
+                fs::canonicalize(directory)
+                    .or_else(|_| path::absolute(directory))
+                    .ok()
+            })
+            .collect();
+
+        Ok(ScanFilters {
+            excluded,
+            safeguards: Safeguards::new(self.configurations.get_safeguard_options())?,
+            ignores: IgnoreList::load(root, self.configurations.get_ignore_patterns()),
+        })
     }
 
-    pub fn delete_rule(&mut self, pattern: &str) {
-        self.configurations.delete_dynamic_rule(pattern);
+    /// Confines the actions of a plan to the destination directory, see `Actions::confine`.
+    /// A destination outside of it is only allowed if the rule currently matching the
+    /// file allows it.
+    pub fn confine_plan(&self, actions: &mut Actions) {
+        let destination_directory = self.configurations.get_destination_directory();
+        let destination_root =
+            path::absolute(destination_directory).unwrap_or_else(|_| destination_directory.clone());
+        actions.confine(destination_root, |file, relative_path| {
+            self.configurations
+                .retrieve_destination_directory(file, relative_path)
+                .is_some_and(|rule_match| rule_match.allow_absolute)
+        });
     }
 
-    /// Generates actions
-    pub fn generate_actions(&self) -> Actions {
+    /// Creates an empty set of actions for the canonical working directory `root`
+    fn new_actions(&self, root: &Path) -> Actions {
         let mut actions = Actions::new();
-        if let Ok(absolute_path) = fs::canonicalize(self.configurations.get_working_directory()) {
-            match fs::read_dir(self.configurations.get_working_directory()) {
-                Ok(listings) => {
-                    for listing in listings {
-                        let entry = match listing {
-                            Ok(e) => e.path(),
-                            Err(err) => {
-                                error!("{}", err);
-                                continue;
-                            }
-                        };
-
-                        if !entry.is_file() {
-                            continue;
-                        }
+        actions.set_hash_sources(self.hash_sources);
+        let destination_root = path::absolute(self.configurations.get_destination_directory())
+            .unwrap_or_else(|_| self.configurations.get_destination_directory().clone());
+        actions.set_header(PlanHeader::new(
+            root.to_path_buf(),
+            destination_root,
+            self.configurations.hash(),
+        ));
+        actions
+    }
 
-                        // increment total files counter
-                        actions.total_files_scanned += 1;
-
-                        let file_name = match entry.file_name().and_then(|n| n.to_str()) {
-                            Some(name) => name,
-                            None => {
-                                // increment error counter
-                                actions.total_files_errors += 1;
-                                error!("Invalid file name: {}", entry.display());
-                                continue;
-                            }
-                        };
-                        if let Some(destination) = self
-                            .configurations
-                            .retrieve_destination_directory(file_name)
-                        {
-                            let destination = Path::new(&destination).join(file_name);
-                            let absolute_file_path = absolute_path.join(file_name);
-                            actions.add_action(absolute_file_path, destination);
-                        } else {
-                            // increment skipped files counter
-                            actions.total_files_skipped += 1;
+    /// Scans a directory for files to organise, `relative` is the path of the directory
+    /// relative to the working directory and `depth` its nesting level. Every file is
+    /// passed to `visit` along with the relative path of its directory.
+    ///
+    /// Fails if the directory cannot be listed, otherwise returns the number of entries
+    /// and subdirectories which could not be listed.
+    fn scan_directory(
+        &self,
+        directory: &Path,
+        relative: &Path,
+        depth: usize,
+        filters: &ScanFilters,
+        visit: &mut dyn FnMut(&Path, &Path),
+    ) -> Result<u32, String> {
+        let scan_options = self.configurations.get_scan_options();
+        let listings = fs::read_dir(directory).map_err(|err| {
+            format!(
+                "Error occurred while listing directory entries of {}: {}",
+                directory.display(),
+                err
+            )
+        })?;
+
+        let mut errors = 0;
+        for listing in listings {
+            let entry = match listing {
+                Ok(e) => e,
+                Err(err) => {
+                    error!("{}", err);
+                    errors += 1;
+                    continue;
+                }
+            };
+            let entry_path = entry.path();
+
+            // Symbolic links to directories are not followed, to avoid loops
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                let within_depth = scan_options.max_depth.is_none_or(|max| depth < max);
+                let relative_directory = relative.join(entry.file_name());
+                if scan_options.recursive
+                    && within_depth
+                    && !filters.excluded.contains(&entry_path)
+                    && !filters.ignores.is_ignored(&relative_directory, true)
+                {
+                    match self.scan_directory(
+                        &entry_path,
+                        &relative_directory,
+                        depth + 1,
+                        filters,
+                        visit,
+                    ) {
+                        Ok(subdirectory_errors) => errors += subdirectory_errors,
+                        Err(err) => {
+                            error!("{} !", err);
+                            errors += 1;
                         }
                     }
                 }
-                Err(err) => error!("Error occurred while listing directory entries: {}", err),
+                continue;
+            }
+
+            if !entry_path.is_file() {
+                continue;
+            }
+
+            visit(&entry_path, relative);
+        }
+
+        Ok(errors)
+    }
+
+    /// Matches a single file against the rules and plans the action of the first matching
+    /// rule, `relative` is the path of its directory relative to the working directory.
+    /// Ignored files are left alone, files which may still be written are deferred and
+    /// the reason is returned.
+    fn plan_file(
+        &self,
+        file: &Path,
+        relative: &Path,
+        filters: &ScanFilters,
+        actions: &mut Actions,
+    ) -> Option<Deferral> {
+        // increment total files counter
+        actions.total_files_scanned += 1;
+        let file_name = match file.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name,
+            None => {
+                // increment error counter
+                actions.total_files_errors += 1;
+                error!("Invalid file name: {}", file.display());
+                return None;
             }
+        };
+
+        if filters.ignores.is_ignored(&relative.join(file_name), false) {
+            // increment ignored files counter
+            actions.total_files_ignored += 1;
+            return None;
         }
 
-        actions
+        if let Some(deferral) = filters
+            .safeguards
+            .check(file, fs::metadata(file).ok().as_ref())
+        {
+            // increment deferred files counter
+            actions.total_files_deferred += 1;
+            println!("Deferred file {} as {}", file.display(), deferral);
+            return Some(deferral);
+        }
+
+        if let Some(rule_match) = self
+            .configurations
+            .retrieve_destination_directory(file, &relative.join(file_name))
+        {
+            let destination = self.destination_path(&rule_match, relative, file_name);
+            actions.plan_action(
+                rule_match.action,
+                file.to_path_buf(),
+                destination,
+                rule_match.on_conflict,
+                rule_match.allow_absolute,
+            );
+        } else {
+            // increment skipped files counter
+            actions.total_files_skipped += 1;
+        }
+
+        None
+    }
+
+    /// Path where a file matched by a rule is placed, `relative` is the path of its
+    /// directory relative to the working directory.
+    fn destination_path(
+        &self,
+        rule_match: &RuleMatch,
+        relative: &Path,
+        file_name: &str,
+    ) -> PathBuf {
+        if self.configurations.get_scan_options().keep_structure {
+            rule_match.destination.join(relative).join(file_name)
+        } else {
+            rule_match.destination.join(file_name)
+        }
+    }
+
+    /// Explains what organising would do with a file and why, every rule is evaluated.
+    /// A name which does not exist is explained as a file in the working directory.
+    pub fn explain(&self, target: &Path) -> Result<FileExplanation, String> {
+        let working_directory = self.configurations.get_working_directory();
+        let root = fs::canonicalize(working_directory).map_err(|err| {
+            format!(
+                "Unable to resolve the working directory {}: {}",
+                working_directory.display(),
+                err
+            )
+        })?;
+        let file = match fs::canonicalize(target) {
+            Ok(file) => file,
+            Err(_) => root.join(target),
+        };
+        let file_name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("Invalid file name: {}", target.display()))?;
+        // Files outside of the working directory are explained as if they were inside
+        let relative_path = file
+            .strip_prefix(&root)
+            .map_or_else(|_| PathBuf::from(file_name), Path::to_path_buf);
+        let relative = relative_path.parent().unwrap_or(Path::new(""));
+
+        let filters = self.scan_filters(&root)?;
+        let rules = self.configurations.explain(&file, &relative_path);
+        let destination = rules
+            .rule_match
+            .as_ref()
+            .map(|rule_match| self.destination_path(rule_match, relative, file_name));
+        Ok(FileExplanation {
+            ignored: filters.ignores.is_ignored(&relative_path, false),
+            deferral: fs::metadata(&file)
+                .ok()
+                .and_then(|metadata| filters.safeguards.check(&file, Some(&metadata))),
+            file,
+            relative_path,
+            rules,
+            destination,
+        })
+    }
+
+    /// Analyses the rules for overlapping and shadowed rules, rules matching nothing and
+    /// destinations leaving the destination root. The files of the working directory are
+    /// sampled as a scan would find them if it is given as `sample_directory`, or the
+    /// names of a built-in corpus otherwise.
+    pub fn lint_rules(&self, sample_directory: Option<&Path>) -> Result<LintReport, String> {
+        let samples = if let Some(sample_directory) = sample_directory {
+            let root = fs::canonicalize(sample_directory).map_err(|err| {
+                format!(
+                    "Unable to resolve the working directory {}: {}",
+                    sample_directory.display(),
+                    err
+                )
+            })?;
+            let filters = self.scan_filters(&root)?;
+            let mut samples = vec![];
+            self.scan_directory(&root, Path::new(""), 0, &filters, &mut |file, relative| {
+                let Some(file_name) = file.file_name() else {
+                    return;
+                };
+                let relative_path = relative.join(file_name);
+                if !filters.ignores.is_ignored(&relative_path, false) {
+                    samples.push((
+                        relative_path.display().to_string(),
+                        self.configurations.explain(file, &relative_path),
+                    ));
+                }
+            })?;
+            samples
+        } else {
+            CORPUS
+                .iter()
+                .map(|name| {
+                    (
+                        name.to_string(),
+                        self.configurations.explain_name(Path::new(name)),
+                    )
+                })
+                .collect()
+        };
+
+        let mut report = lint::analyse(&samples, sample_directory.is_some());
+        report.escaping = self
+            .retrieve_rules()
+            .iter()
+            .filter(|rule| rule.action.has_destination() && !rule.allow_absolute)
+            .filter_map(|rule| {
+                escapes_root(&rule.destination).map(|reason| {
+                    (
+                        format!("{} -> {}", rule.describe(), rule.describe_action()),
+                        reason,
+                    )
+                })
+            })
+            .collect();
+        Ok(report)
+    }
+
+    /// Watches the working directory and organises every new file once its size and
+    /// modification time did not change for the settle time, so that files which are
+    /// still being written are left alone. The files already present are organised first.
+    ///
+    /// `execute` is called with the actions of every batch of settled files, the
+    /// watching only stops on an error.
+    #[cfg(target_os = "linux")]
+    pub fn watch(&self, settle: Duration, mut execute: impl FnMut(&mut Actions)) -> io::Result<()> {
+        let root = fs::canonicalize(self.configurations.get_working_directory())?;
+        let scan_options = self.configurations.get_scan_options();
+        let (mut watcher, existing_files) = Watcher::new(
+            &root,
+            scan_options.recursive,
+            scan_options.max_depth,
+            self.scan_filters(&root).map_err(io::Error::other)?.excluded,
+        )?;
+        println!("Watching {} for new files", root.display());
+
+        let mut pending = PendingFiles::new(settle);
+        pending.track(existing_files);
+        loop {
+            let settled = pending.settled();
+            if !settled.is_empty() {
+                // Resolved for every batch, to pick up changes of the ignore file
+                let filters = self.scan_filters(&root).map_err(io::Error::other)?;
+                let mut actions = self.new_actions(&root);
+                let mut deferred = vec![];
+                for file in settled {
+                    let relative = file
+                        .parent()
+                        .and_then(|directory| directory.strip_prefix(&root).ok())
+                        .unwrap_or(Path::new(""));
+                    let deferral = self.plan_file(&file, relative, &filters, &mut actions);
+                    if deferral.is_some_and(|deferral| deferral.is_temporary()) {
+                        deferred.push(file);
+                    }
+                }
+                // Deferred files are checked again, as they might not change anymore
+                pending.track(deferred);
+
+                if !actions.is_empty() {
+                    execute(&mut actions);
+                    self.prune_empty_directories(&mut actions);
+                }
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+            pending.track(watcher.changed_files()?);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn watch(&self, _settle: Duration, _execute: impl FnMut(&mut Actions)) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "watching is only supported on linux",
+        ))
+    }
+
+    /// Removes the source directories which were emptied by the executed actions,
+    /// if enabled in the scanning options.
+    pub fn prune_empty_directories(&self, actions: &mut Actions) {
+        if !self
+            .configurations
+            .get_scan_options()
+            .prune_empty_directories
+        {
+            return;
+        }
+
+        match fs::canonicalize(self.configurations.get_working_directory()) {
+            Ok(root) => actions.prune_empty_directories(&root),
+            Err(err) => error!(
+                "Unable to resolve the working directory {}: {}",
+                self.configurations.get_working_directory().display(),
+                err
+            ),
+        }
     }
 }
@@ -0,0 +1,223 @@
+/// Translates a glob into an unanchored regex: `*` and `?` do not match a slash, `**`
+/// matches across directories, `[...]` is a character class and `\` escapes a character.
+/// With `braces`, `{a,b}` matches either of the alternatives, which may be globs themselves.
+pub fn to_regex(glob: &str, braces: bool) -> Result<String, String> {
+    let chars: Vec<char> = glob.chars().collect();
+    translate(&chars, braces).map_err(|err| format!("{} in glob {}", err, glob))
+}
+
+fn translate(chars: &[char], braces: bool) -> Result<String, String> {
+    let mut regex = String::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        index += 1;
+        match c {
+            '*' if chars.get(index) == Some(&'*') => {
+                index += 1;
+                let at_start = index == 2 || chars[index - 3] == '/';
+                match chars.get(index) {
+                    // `**/` matches zero or more directories
+                    Some('/') if at_start => {
+                        index += 1;
+                        regex.push_str("(?:.*/)?");
+                    }
+                    // A trailing `**` matches everything inside
+                    None if at_start => regex.push_str(".*"),
+                    _ => regex.push_str("[^/]*"),
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match class_end(chars, index) {
+                Some(end) => {
+                    regex.push('[');
+                    let mut class = &chars[index..end];
+                    if let Some('!' | '^') = class.first() {
+                        regex.push('^');
+                        class = &class[1..];
+                    }
+                    for &c in class {
+                        if matches!(c, '\\' | '[' | ']' | '&' | '~' | '^') {
+                            regex.push('\\');
+                        }
+                        regex.push(c);
+                    }
+                    regex.push(']');
+                    index = end + 1;
+                }
+                None => regex.push_str("\\["),
+            },
+            '{' if braces => {
+                let end = brace_end(chars, index).ok_or("unclosed {")?;
+                let alternatives = split_alternatives(&chars[index..end])
+                    .into_iter()
+                    .map(|alternative| translate(alternative, braces))
+                    .collect::<Result<Vec<_>, _>>()?;
+                regex.push_str(&format!("(?:{})", alternatives.join("|")));
+                index = end + 1;
+            }
+            '\\' => {
+                if let Some(&escaped) = chars.get(index) {
+                    index += 1;
+                    regex.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    Ok(regex)
+}
+
+/// Index of the `]` closing a character class which starts at `start`, a `]` right
+/// after the opening bracket (or its negation) is taken literally.
+fn class_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut index = start;
+    if let Some('!' | '^') = chars.get(index) {
+        index += 1;
+    }
+    if chars.get(index) == Some(&']') {
+        index += 1;
+    }
+    chars[index.min(chars.len())..]
+        .iter()
+        .position(|&c| c == ']')
+        .map(|position| index + position)
+}
+
+/// Index of the `}` closing the braces which start at `start`, nested braces included
+fn brace_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = start;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Splits the content of braces at the commas which are not nested in inner braces
+fn split_alternatives(chars: &[char]) -> Vec<&[char]> {
+    let mut alternatives = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&chars[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    alternatives.push(&chars[start.min(chars.len())..]);
+    alternatives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn matches(glob: &str, name: &str) -> bool {
+        Regex::new(&format!("^{}$", to_regex(glob, true).unwrap()))
+            .unwrap()
+            .is_match(name)
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(matches("*.txt", ".txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("file?.log", "file1.log"));
+        assert!(!matches("file?.log", "file10.log"));
+        assert!(!matches("*.txt", "dir/notes.txt"));
+    }
+
+    #[test]
+    fn matches_across_directories() {
+        assert!(matches("**/*.txt", "notes.txt"));
+        assert!(matches("**/*.txt", "a/b/notes.txt"));
+        assert!(matches("docs/**", "docs/a/b.txt"));
+        assert!(!matches("docs/**", "other/a.txt"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        // `**` inside a name is a plain `*`
+        assert!(matches("a**b", "axxb"));
+        assert!(!matches("a**b", "a/b"));
+    }
+
+    #[test]
+    fn matches_braces() {
+        assert!(matches("*.{mp3,wav}", "song.mp3"));
+        assert!(matches("*.{mp3,wav}", "song.wav"));
+        assert!(!matches("*.{mp3,wav}", "song.ogg"));
+        assert!(matches("{report,summary}-*.pdf", "summary-2024.pdf"));
+        // Nested braces and globs inside the alternatives
+        assert!(matches("*.{tar.{gz,bz2},zip}", "backup.tar.bz2"));
+        assert!(matches("*.{jp?g,png}", "photo.jpeg"));
+        assert!(matches("a{,b}", "a"));
+        assert!(matches("a{,b}", "ab"));
+        // An escaped comma is part of the alternative
+        assert!(matches("{a\\,b,c}", "a,b"));
+        assert!(!matches("{a\\,b,c}", "a"));
+    }
+
+    #[test]
+    fn leaves_braces_alone_without_brace_expansion() {
+        let regex = Regex::new(&format!("^{}$", to_regex("{a,b}", false).unwrap())).unwrap();
+        assert!(regex.is_match("{a,b}"));
+        assert!(!regex.is_match("a"));
+    }
+
+    #[test]
+    fn rejects_unclosed_braces() {
+        let err = to_regex("*.{mp3,wav", true).unwrap_err();
+        assert_eq!(err, "unclosed { in glob *.{mp3,wav");
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("[a-c]*", "beta"));
+        assert!(matches("[!abc].txt", "d.txt"));
+        assert!(!matches("[!abc].txt", "a.txt"));
+        assert!(matches("[^abc].txt", "d.txt"));
+        // A `]` right after the opening bracket is literal
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[!]a]", "b"));
+        assert!(!matches("[!]a]", "]"));
+        // Regex class syntax inside is taken literally
+        assert!(matches("[&~]", "~"));
+        assert!(matches("[\\]", "\\"));
+        // An unclosed class is a literal bracket
+        assert!(matches("[abc", "[abc"));
+    }
+
+    #[test]
+    fn escapes_regex_syntax() {
+        assert!(matches("a+b(1).txt", "a+b(1).txt"));
+        assert!(!matches("a+b(1).txt", "aab1.txt"));
+        assert!(matches("price$^.txt", "price$^.txt"));
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+        assert!(matches("what\\?", "what?"));
+        // A trailing backslash escapes nothing
+        assert!(matches("name\\", "name"));
+    }
+}
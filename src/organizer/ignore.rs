@@ -0,0 +1,260 @@
+use super::config_file::{ConfigFormat, DIRECTORY_CONFIG_STEM};
+use super::glob;
+use log::error;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+/// Ignore file in the working directory, with the same syntax as .gitignore
+pub const IGNORE_FILE: &str = ".fsorgignore";
+
+#[derive(Debug)]
+struct Pattern {
+    /// Matches the path relative to the working directory, with forward slashes
+    regex: Regex,
+
+    /// `!pattern`: includes the matching paths again
+    negated: bool,
+
+    /// `pattern/`: only matches directories
+    directory_only: bool,
+}
+
+#[derive(Debug, Default)]
+/// Gitignore style patterns deciding which files and directories are left alone,
+/// the last matching pattern wins.
+pub struct IgnoreList {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreList {
+    /// Loads the global patterns of the configurations followed by the patterns of
+    /// the ignore file in the working directory, so that the latter take precedence.
+    pub fn load(root: &Path, global_patterns: &[String]) -> Self {
+        let mut ignores = IgnoreList::default();
+        // The ignore file and the directory configurations are never organised
+        let _ = ignores.add(&format!("/{}", IGNORE_FILE));
+        for extension in ConfigFormat::EXTENSIONS {
+            let _ = ignores.add(&format!("/{}.{}", DIRECTORY_CONFIG_STEM, extension));
+        }
+
+        for pattern in global_patterns {
+            if let Err(err) = ignores.add(pattern) {
+                error!("Invalid ignore pattern {}: {}", pattern, err);
+            }
+        }
+
+        let ignore_file = root.join(IGNORE_FILE);
+        if let Ok(contents) = fs::read_to_string(&ignore_file) {
+            for (index, line) in contents.lines().enumerate() {
+                if let Err(err) = ignores.add(line) {
+                    error!(
+                        "Invalid ignore pattern at {}:{}: {}",
+                        ignore_file.display(),
+                        index + 1,
+                        err
+                    );
+                }
+            }
+        }
+
+        ignores
+    }
+
+    /// Adds a line of gitignore syntax, blank lines and comments are skipped
+    pub fn add(&mut self, line: &str) -> Result<(), String> {
+        let mut pattern = trim_trailing_spaces(line);
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return Ok(());
+        }
+
+        // A leading backslash escapes a literal ! or #
+        let negated = pattern.starts_with('!');
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+
+        let directory_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.is_empty() {
+            return Ok(());
+        }
+
+        // A slash anywhere but at the end anchors the pattern at the working directory,
+        // otherwise it matches the name at any depth.
+        let regex = if pattern.contains('/') {
+            format!(
+                "^{}$",
+                glob::to_regex(pattern.trim_start_matches('/'), false)?
+            )
+        } else {
+            format!("^(?:.*/)?{}$", glob::to_regex(pattern, false)?)
+        };
+
+        self.patterns.push(Pattern {
+            regex: Regex::new(&regex).map_err(|err| err.to_string())?,
+            negated,
+            directory_only,
+        });
+        Ok(())
+    }
+
+    /// Checks a path relative to the working directory. As with git, a path inside an
+    /// ignored directory cannot be included again.
+    pub fn is_ignored(&self, relative_path: &Path, is_directory: bool) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+
+        let relative_path = relative_path.to_string_lossy().replace('\\', "/");
+        let mut parent = String::new();
+        for component in relative_path
+            .rsplit_once('/')
+            .map_or("", |(p, _)| p)
+            .split('/')
+        {
+            if component.is_empty() {
+                continue;
+            }
+            if !parent.is_empty() {
+                parent.push('/');
+            }
+            parent.push_str(component);
+            if self.matches(&parent, true) {
+                return true;
+            }
+        }
+
+        self.matches(&relative_path, is_directory)
+    }
+
+    fn matches(&self, relative_path: &str, is_directory: bool) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| {
+                (is_directory || !pattern.directory_only) && pattern.regex.is_match(relative_path)
+            })
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Trailing spaces are removed unless they are escaped with a backslash
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore_list(lines: &[&str]) -> IgnoreList {
+        let mut ignores = IgnoreList::default();
+        for line in lines {
+            ignores.add(line).unwrap();
+        }
+        ignores
+    }
+
+    fn ignored(ignores: &IgnoreList, path: &str) -> bool {
+        ignores.is_ignored(Path::new(path), false)
+    }
+
+    #[test]
+    fn matches_names_at_any_depth() {
+        let ignores = ignore_list(&["*.part", "Thumbs.db"]);
+        assert!(ignored(&ignores, "movie.part"));
+        assert!(ignored(&ignores, "a/b/movie.part"));
+        assert!(ignored(&ignores, "photos/Thumbs.db"));
+        assert!(!ignored(&ignores, "movie.mkv"));
+        assert!(!ignored(&ignores, "Thumbs.db.bak"));
+    }
+
+    #[test]
+    fn anchors_patterns_with_a_slash() {
+        let ignores = ignore_list(&["/todo.txt", "build/*.o"]);
+        assert!(ignored(&ignores, "todo.txt"));
+        assert!(!ignored(&ignores, "notes/todo.txt"));
+        assert!(ignored(&ignores, "build/main.o"));
+        assert!(!ignored(&ignores, "src/build/main.o"));
+        assert!(!ignored(&ignores, "build/sub/main.o"));
+
+        let ignores = ignore_list(&["**/cache/*.tmp"]);
+        assert!(ignored(&ignores, "cache/a.tmp"));
+        assert!(ignored(&ignores, "x/y/cache/a.tmp"));
+    }
+
+    #[test]
+    fn negation_includes_files_again_and_the_last_pattern_wins() {
+        let ignores = ignore_list(&["*.log", "!keep.log"]);
+        assert!(ignored(&ignores, "debug.log"));
+        assert!(!ignored(&ignores, "keep.log"));
+        assert!(!ignored(&ignores, "logs/keep.log"));
+
+        let ignores = ignore_list(&["!keep.log", "*.log"]);
+        assert!(ignored(&ignores, "keep.log"));
+    }
+
+    #[test]
+    fn directory_patterns_only_match_directories() {
+        let ignores = ignore_list(&["node_modules/"]);
+        assert!(ignores.is_ignored(Path::new("node_modules"), true));
+        assert!(!ignores.is_ignored(Path::new("node_modules"), false));
+        // Files inside an ignored directory are ignored as well
+        assert!(ignored(&ignores, "node_modules/lib/index.js"));
+        assert!(ignored(&ignores, "web/node_modules/index.js"));
+    }
+
+    #[test]
+    fn files_inside_ignored_directories_cannot_be_included_again() {
+        let ignores = ignore_list(&["private/", "!private/keep.txt"]);
+        assert!(ignored(&ignores, "private/keep.txt"));
+
+        // Ignoring the content rather than the directory allows including a file again
+        let ignores = ignore_list(&["private/*", "!private/keep.txt"]);
+        assert!(!ignored(&ignores, "private/keep.txt"));
+        assert!(ignored(&ignores, "private/other.txt"));
+    }
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        let ignores = ignore_list(&["", "   ", "# comment", "/"]);
+        assert!(ignores.patterns.is_empty());
+        assert!(!ignored(&ignores, "# comment"));
+    }
+
+    #[test]
+    fn escapes_leading_special_characters_and_trailing_spaces() {
+        let ignores = ignore_list(&[
+            "\\#notes.txt",
+            "\\!important.txt",
+            "spaced\\ ",
+            "trimmed   ",
+        ]);
+        assert!(ignored(&ignores, "#notes.txt"));
+        assert!(ignored(&ignores, "!important.txt"));
+        assert!(ignored(&ignores, "spaced "));
+        assert!(!ignored(&ignores, "spaced"));
+        assert!(ignored(&ignores, "trimmed"));
+    }
+
+    #[test]
+    fn always_ignores_its_own_files() {
+        let ignores = IgnoreList::load(Path::new("/nonexistent"), &[]);
+        assert!(ignored(&ignores, IGNORE_FILE));
+        assert!(ignored(&ignores, ".fsorg.json"));
+        assert!(ignored(&ignores, ".fsorg.yml"));
+        assert!(!ignored(&ignores, "sub/.fsorg.json"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(IgnoreList::default().add("*.{a,b").is_ok());
+        assert!(IgnoreList::default().add("[z-a]").is_err());
+    }
+}
@@ -0,0 +1,474 @@
+use super::actions::ActionKind;
+use super::trash;
+use super::{get_home_dir, modified_nanos, move_file_safely};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{self, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Outcome of an executed action
+pub enum Outcome {
+    /// The action was carried out, at a free destination if it has one
+    #[serde(alias = "moved")]
+    Completed,
+    /// The file was placed under a new name
+    Renamed,
+    /// The file replaced an existing file, which cannot be restored
+    Overwritten,
+    /// The file was removed as a duplicate of the destination
+    DuplicateRemoved,
+    /// The file was left in place
+    Skipped,
+    /// The action failed
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A single record of the journal, one per executed action
+pub struct JournalEntry {
+    #[serde(default)]
+    pub kind: ActionKind,
+
+    pub source: PathBuf,
+
+    /// Location of the file inside the trash for trashed files, empty for deleted files
+    pub destination: PathBuf,
+
+    /// Seconds since the unix epoch when the action was executed
+    pub timestamp: u64,
+
+    pub outcome: Outcome,
+
+    /// Size of the destination file after the action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+
+    /// Modification time of the destination file after the action, nanoseconds since the unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JournalEntry {
+    pub fn new(kind: ActionKind, source: &Path, destination: &Path, outcome: Outcome) -> Self {
+        let (size, modified) = match fs::symlink_metadata(destination) {
+            Ok(metadata) => (Some(metadata.len()), modified_nanos(&metadata)),
+            Err(_) => (None, None),
+        };
+        let destination = if destination.as_os_str().is_empty() {
+            PathBuf::new()
+        } else {
+            path::absolute(destination).unwrap_or_else(|_| destination.to_path_buf())
+        };
+
+        JournalEntry {
+            kind,
+            source: path::absolute(source).unwrap_or_else(|_| source.to_path_buf()),
+            destination,
+            timestamp: unix_timestamp(),
+            outcome,
+            size,
+            modified,
+            error: None,
+        }
+    }
+
+    pub fn failed(kind: ActionKind, source: &Path, destination: &Path, err: &io::Error) -> Self {
+        JournalEntry {
+            error: Some(err.to_string()),
+            ..JournalEntry::new(kind, source, destination, Outcome::Failed)
+        }
+    }
+}
+
+/// Seconds since the unix epoch
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Default location of the journals: $XDG_STATE_HOME/fsorg/journals or ~/.local/state/fsorg/journals
+pub fn default_journal_file() -> PathBuf {
+    let directory = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|d| d.is_absolute())
+        .or_else(|| get_home_dir().map(|d| d.join(".local").join("state")))
+        .map(|d| d.join("fsorg").join("journals"))
+        .unwrap_or_default();
+
+    directory.join(format!("journal-{}.jsonl", unix_timestamp()))
+}
+
+/// Append only record of the executed actions, every entry is flushed to the disk
+/// as soon as it is written so that the journal survives an interrupted run.
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent_dir) = path.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Journal { file, path })
+    }
+
+    /// Creates a journal at the default location, see `default_journal_file`. A name
+    /// which is already taken, e.g. by another run within the same second, gets a
+    /// numeric suffix.
+    pub fn create_default() -> io::Result<Self> {
+        Journal::create_unique(default_journal_file())
+    }
+
+    /// Creates a journal at the path, or next to it with a numeric suffix if it is taken
+    fn create_unique(path: PathBuf) -> io::Result<Self> {
+        let mut candidate = path.clone();
+        let mut suffix = 0;
+        loop {
+            match Journal::create(candidate) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists && suffix < 1000 => {
+                    suffix += 1;
+                    candidate = path.with_extension(format!("{}.jsonl", suffix));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, entry: &JournalEntry) {
+        let result = serde_json::to_string(entry)
+            .map_err(io::Error::other)
+            .and_then(|line| self.file.write_all(format!("{}\n", line).as_bytes()))
+            .and_then(|()| self.file.sync_data());
+        if let Err(err) = result {
+            error!(
+                "Unable to write to journal {}: {} !",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    /// Reads all the entries of a journal, in the order they were recorded
+    pub fn read(path: &Path) -> io::Result<Vec<JournalEntry>> {
+        let mut entries = vec![];
+        for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                // The last line might be incomplete when the run was interrupted
+                Err(err) => warn!(
+                    "Ignoring invalid journal entry at {}:{}: {}",
+                    path.display(),
+                    index + 1,
+                    err
+                ),
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Statistics of an undo run
+pub struct UndoReport {
+    pub total_files_restored: u32,
+    pub total_files_missing: u32,
+    pub total_files_changed: u32,
+    pub total_files_errors: u32,
+}
+
+/// Reverses the actions recorded in a journal, in the reverse order of execution.
+/// Files which went missing or were modified since the run are reported and left untouched.
+/// Copies and links are removed again, deleted files cannot be restored.
+pub fn undo(journal_file: &Path) -> io::Result<UndoReport> {
+    let mut report = UndoReport::default();
+
+    for entry in Journal::read(journal_file)?.iter().rev() {
+        let restore_by_copy = match entry.outcome {
+            Outcome::Completed | Outcome::Renamed | Outcome::Overwritten => false,
+            // The removed file had the same content as the destination
+            Outcome::DuplicateRemoved => true,
+            Outcome::Skipped | Outcome::Failed => continue,
+        };
+
+        if entry.kind == ActionKind::Delete {
+            report.total_files_errors += 1;
+            error!(
+                "Cannot restore {} as it was deleted",
+                entry.source.display()
+            );
+            continue;
+        }
+
+        let metadata = match fs::symlink_metadata(&entry.destination) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                report.total_files_missing += 1;
+                println!("Missing file {}", entry.destination.display());
+                continue;
+            }
+        };
+
+        if entry.size != Some(metadata.len()) || entry.modified != modified_nanos(&metadata) {
+            report.total_files_changed += 1;
+            println!(
+                "Changed file {} since it was organised, not restoring it",
+                entry.destination.display()
+            );
+            continue;
+        }
+
+        if !entry.kind.removes_source() {
+            match fs::remove_file(&entry.destination) {
+                Ok(()) => {
+                    report.total_files_restored += 1;
+                    println!("Removed file {}", entry.destination.display());
+                }
+                Err(err) => {
+                    report.total_files_errors += 1;
+                    error!(
+                        "An error occurred while removing file {}: {}",
+                        entry.destination.display(),
+                        err
+                    );
+                }
+            }
+            continue;
+        }
+
+        if entry.source.symlink_metadata().is_ok() {
+            report.total_files_errors += 1;
+            error!(
+                "Cannot restore {} as {} already exists",
+                entry.destination.display(),
+                entry.source.display()
+            );
+            continue;
+        }
+
+        let result = if restore_by_copy {
+            entry
+                .source
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::copy(&entry.destination, &entry.source).map(|_| ()))
+        } else if entry.kind == ActionKind::Trash {
+            trash::restore(&entry.destination, &entry.source)
+        } else {
+            move_file_safely(&entry.destination, &entry.source)
+        };
+
+        match result {
+            Ok(()) => {
+                report.total_files_restored += 1;
+                println!(
+                    "Restored file {} to {}",
+                    entry.destination.display(),
+                    entry.source.display()
+                );
+                if entry.outcome == Outcome::Overwritten {
+                    warn!(
+                        "The file previously overwritten at {} cannot be restored",
+                        entry.destination.display()
+                    );
+                }
+            }
+            Err(err) => {
+                report.total_files_errors += 1;
+                error!(
+                    "An error occurred while restoring file {} to {}: {}",
+                    entry.destination.display(),
+                    entry.source.display(),
+                    err
+                );
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A temporary directory, removed when dropped
+    struct Directory(PathBuf);
+
+    impl Directory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "fsorg-journal-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Directory(path)
+        }
+
+        fn file(&self, name: &str, content: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Moves a file and records the move in the journal
+    fn record_move(journal: &mut Journal, source: &Path, destination: &Path) {
+        move_file_safely(source, destination).unwrap();
+        journal.record(&JournalEntry::new(
+            ActionKind::Move,
+            source,
+            destination,
+            Outcome::Completed,
+        ));
+    }
+
+    #[test]
+    fn undoes_in_reverse_order() {
+        let directory = Directory::new("reverse-order");
+        let source = directory.file("a.txt", "a");
+        let first = directory.0.join("Text").join("a.txt");
+        let second = directory.0.join("Archive").join("a.txt");
+        let mut journal = Journal::create(directory.0.join("journal.jsonl")).unwrap();
+        // The second move only succeeds to be undone after the first one
+        record_move(&mut journal, &source, &first);
+        record_move(&mut journal, &first, &second);
+
+        let report = undo(journal.get_path()).unwrap();
+        assert_eq!(report.total_files_restored, 2);
+        assert_eq!(report.total_files_missing, 0);
+        assert_eq!(report.total_files_errors, 0);
+        assert_eq!(fs::read_to_string(&source).unwrap(), "a");
+        assert!(!first.exists());
+        assert!(!second.exists());
+    }
+
+    #[test]
+    fn leaves_changed_and_missing_files_alone() {
+        let directory = Directory::new("changed-missing");
+        let changed = directory.file("changed.txt", "a");
+        let missing = directory.file("missing.txt", "b");
+        let kept = directory.file("kept.txt", "c");
+        let destination = directory.0.join("Text");
+        let mut journal = Journal::create(directory.0.join("journal.jsonl")).unwrap();
+        for source in [&changed, &missing, &kept] {
+            record_move(
+                &mut journal,
+                source,
+                &destination.join(source.file_name().unwrap()),
+            );
+        }
+        fs::write(destination.join("changed.txt"), "modified").unwrap();
+        fs::remove_file(destination.join("missing.txt")).unwrap();
+
+        let report = undo(journal.get_path()).unwrap();
+        assert_eq!(report.total_files_restored, 1);
+        assert_eq!(report.total_files_changed, 1);
+        assert_eq!(report.total_files_missing, 1);
+        assert_eq!(report.total_files_errors, 0);
+        assert!(kept.exists());
+        assert!(!changed.exists());
+        assert_eq!(
+            fs::read_to_string(destination.join("changed.txt")).unwrap(),
+            "modified"
+        );
+    }
+
+    #[test]
+    fn restores_removed_duplicates_by_copying() {
+        let directory = Directory::new("duplicate");
+        let existing = directory.file("existing.txt", "same");
+        let duplicate = directory.0.join("Downloads").join("duplicate.txt");
+        let mut journal = Journal::create(directory.0.join("journal.jsonl")).unwrap();
+        journal.record(&JournalEntry::new(
+            ActionKind::Move,
+            &duplicate,
+            &existing,
+            Outcome::DuplicateRemoved,
+        ));
+
+        let report = undo(journal.get_path()).unwrap();
+        assert_eq!(report.total_files_restored, 1);
+        assert_eq!(fs::read_to_string(&duplicate).unwrap(), "same");
+        // The destination was there before the run, so it stays
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "same");
+    }
+
+    #[test]
+    fn skips_skipped_failed_and_deleted_entries() {
+        let directory = Directory::new("not-undone");
+        let source = directory.file("a.txt", "a");
+        let mut journal = Journal::create(directory.0.join("journal.jsonl")).unwrap();
+        for outcome in [Outcome::Skipped, Outcome::Failed] {
+            journal.record(&JournalEntry::new(
+                ActionKind::Move,
+                &source,
+                &directory.0.join("b.txt"),
+                outcome,
+            ));
+        }
+        journal.record(&JournalEntry::new(
+            ActionKind::Delete,
+            &directory.0.join("deleted.txt"),
+            Path::new(""),
+            Outcome::Completed,
+        ));
+
+        let report = undo(journal.get_path()).unwrap();
+        assert_eq!(report.total_files_restored, 0);
+        assert_eq!(report.total_files_missing, 0);
+        assert_eq!(report.total_files_errors, 1);
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn suffixes_journals_created_within_the_same_second() {
+        let directory = Directory::new("suffix");
+        let path = directory.0.join("journal-1700000000.jsonl");
+        let journals: Vec<PathBuf> = (0..3)
+            .map(|_| Journal::create_unique(path.clone()).unwrap().path)
+            .collect();
+        assert_eq!(
+            journals,
+            [
+                path.clone(),
+                directory.0.join("journal-1700000000.1.jsonl"),
+                directory.0.join("journal-1700000000.2.jsonl"),
+            ]
+        );
+        assert!(Journal::create(path).is_err());
+    }
+}
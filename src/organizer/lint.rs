@@ -0,0 +1,380 @@
+use super::configurations::Explanation;
+
+/// File names of common file types, sampled when no source directory is given
+pub const CORPUS: &[&str] = &[
+    "photo.jpg",
+    "IMG_0001.JPG",
+    "image.jpeg",
+    "screenshot.png",
+    "animation.gif",
+    "scan.bmp",
+    "picture.webp",
+    "scan.tiff",
+    "photo.heic",
+    "drawing.svg",
+    "document.pdf",
+    "letter.doc",
+    "report.docx",
+    "budget.xls",
+    "budget.xlsx",
+    "slides.ppt",
+    "slides.pptx",
+    "notes.odt",
+    "sheet.ods",
+    "readme.txt",
+    "notes.md",
+    "data.csv",
+    "letter.rtf",
+    "book.epub",
+    "movie.mp4",
+    "clip.mkv",
+    "video.avi",
+    "clip.mov",
+    "stream.webm",
+    "old.flv",
+    "song.mp3",
+    "sound.wav",
+    "track.ogg",
+    "album.flac",
+    "audio.m4a",
+    "archive.zip",
+    "archive.rar",
+    "archive.7z",
+    "backup.tar.gz",
+    "backup.tar.bz2",
+    "backup.tar.xz",
+    "setup.exe",
+    "installer.msi",
+    "package.deb",
+    "package.rpm",
+    "install.sh",
+    "script.bat",
+    "disk.iso",
+    "app.dmg",
+    "main.rs",
+    "main.cpp",
+    "main.c",
+    "header.h",
+    "header.hpp",
+    "script.py",
+    "Main.java",
+    "main.go",
+    "app.rb",
+    "Program.cs",
+    "App.swift",
+    "index.html",
+    "style.css",
+    "app.js",
+    "config.json",
+    "config.yaml",
+    "data.xml",
+    "database.sqlite",
+    "font.ttf",
+    "download.part",
+    "Makefile",
+    "LICENSE",
+];
+
+/// Two rules matching the same sample files, only the earlier one takes them
+pub struct Overlap {
+    /// Index of the earlier rule in evaluation order
+    pub earlier: usize,
+
+    /// Index of the later rule in evaluation order
+    pub later: usize,
+
+    /// Number of sample files matched by both
+    pub count: usize,
+
+    /// One of the sample files matched by both
+    pub example: String,
+
+    /// Whether the earlier rule surely takes all of those files
+    pub taken_by_earlier: bool,
+}
+
+/// A rule which matches sample files, all of which are taken by earlier rules
+pub struct Shadowing {
+    pub rule: usize,
+
+    /// The earlier rules taking the files
+    pub shadowed_by: Vec<usize>,
+}
+
+#[derive(Default)]
+/// Findings of the analysis of the rules, rules are referred to by their index in
+/// evaluation order
+pub struct LintReport {
+    /// Whether the samples are real files, or only the names of the built-in corpus
+    pub real_files: bool,
+
+    /// Number of sampled files
+    pub samples: usize,
+
+    /// Description of every rule in evaluation order
+    pub rules: Vec<String>,
+
+    pub overlaps: Vec<Overlap>,
+    pub shadowed: Vec<Shadowing>,
+
+    /// Rules which match none of the sample files
+    pub unmatched: Vec<usize>,
+
+    /// Rules checking the content, which cannot be judged by the names of the corpus
+    pub unjudged: Vec<usize>,
+
+    /// Rules whose destination leaves the destination root, along with the reason
+    pub escaping: Vec<(String, &'static str)>,
+}
+
+impl LintReport {
+    pub fn has_findings(&self) -> bool {
+        !self.overlaps.is_empty()
+            || !self.shadowed.is_empty()
+            || !self.unmatched.is_empty()
+            || !self.escaping.is_empty()
+    }
+}
+
+/// Analyses how the rules match the sample files, each explained along with its name.
+/// Real files are judged by the full evaluation of the rules. The names of the corpus
+/// have neither metadata nor content, so a rule with conditions is taken to possibly
+/// match a name matching its pattern but never to surely match it, and rules checking
+/// the content are left out.
+pub fn analyse(samples: &[(String, Explanation)], real_files: bool) -> LintReport {
+    let Some((_, first)) = samples.first() else {
+        return LintReport {
+            real_files,
+            ..Default::default()
+        };
+    };
+    let mut report = LintReport {
+        real_files,
+        samples: samples.len(),
+        rules: first
+            .evaluations
+            .iter()
+            .map(|evaluation| evaluation.description.clone())
+            .collect(),
+        ..Default::default()
+    };
+
+    let judged: Vec<bool> = first
+        .evaluations
+        .iter()
+        .map(|evaluation| real_files || evaluation.mime_matched.is_none())
+        .collect();
+
+    // Which rules possibly and surely match every sample
+    let matches: Vec<(Vec<bool>, Vec<bool>)> = samples
+        .iter()
+        .map(|(_, explanation)| {
+            explanation
+                .evaluations
+                .iter()
+                .zip(&judged)
+                .map(|(evaluation, judged)| {
+                    if !judged {
+                        (false, false)
+                    } else if real_files {
+                        (evaluation.matched(), evaluation.matched())
+                    } else {
+                        (
+                            evaluation.name_matched,
+                            evaluation.name_matched && !evaluation.is_conditional(),
+                        )
+                    }
+                })
+                .unzip()
+        })
+        .collect();
+
+    for (later, &is_judged) in judged.iter().enumerate() {
+        if !is_judged {
+            report.unjudged.push(later);
+            continue;
+        }
+
+        let matching: Vec<usize> = (0..samples.len())
+            .filter(|&sample| matches[sample].0[later])
+            .collect();
+        if matching.is_empty() {
+            report.unmatched.push(later);
+            continue;
+        }
+
+        for earlier in 0..later {
+            let both: Vec<usize> = matching
+                .iter()
+                .copied()
+                .filter(|&sample| matches[sample].0[earlier])
+                .collect();
+            if let Some(&sample) = both.first() {
+                report.overlaps.push(Overlap {
+                    earlier,
+                    later,
+                    count: both.len(),
+                    example: samples[sample].0.clone(),
+                    taken_by_earlier: both.iter().all(|&sample| matches[sample].1[earlier]),
+                });
+            }
+        }
+
+        // The first earlier rule which surely matches takes the file
+        let mut shadowed_by = vec![];
+        for &sample in &matching {
+            match (0..later).find(|&earlier| matches[sample].1[earlier]) {
+                Some(earlier) if !shadowed_by.contains(&earlier) => shadowed_by.push(earlier),
+                Some(_) => {}
+                None => {
+                    shadowed_by.clear();
+                    break;
+                }
+            }
+        }
+        if !shadowed_by.is_empty() {
+            shadowed_by.sort();
+            report.shadowed.push(Shadowing {
+                rule: later,
+                shadowed_by,
+            });
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::configurations::RuleEvaluation;
+
+    /// How a rule is evaluated for a sample: whether its name matched, its MIME type was
+    /// accepted if it checks it, and its conditions held if it has any
+    type Evaluation = (bool, Option<bool>, Option<bool>);
+
+    fn sample(name: &str, evaluations: &[Evaluation]) -> (String, Explanation) {
+        let evaluations = evaluations
+            .iter()
+            .enumerate()
+            .map(
+                |(index, &(name_matched, mime_matched, condition))| RuleEvaluation {
+                    description: format!("rule {}", index),
+                    name_matched,
+                    mime_matched,
+                    conditions: condition
+                        .map(|held| ("min_size", held))
+                        .into_iter()
+                        .collect(),
+                },
+            )
+            .collect();
+        (
+            name.to_string(),
+            Explanation {
+                evaluations,
+                ..Default::default()
+            },
+        )
+    }
+
+    const MATCHED: Evaluation = (true, None, None);
+    const UNMATCHED: Evaluation = (false, None, None);
+
+    #[test]
+    fn reports_overlapping_and_shadowed_rules() {
+        let samples = [
+            sample("a.jpg", &[MATCHED, MATCHED, UNMATCHED]),
+            sample("b.png", &[MATCHED, MATCHED, UNMATCHED]),
+            sample("c.txt", &[UNMATCHED, UNMATCHED, MATCHED]),
+        ];
+        let report = analyse(&samples, false);
+
+        assert_eq!(report.samples, 3);
+        assert_eq!(report.rules, ["rule 0", "rule 1", "rule 2"]);
+        assert_eq!(report.overlaps.len(), 1);
+        let overlap = &report.overlaps[0];
+        assert_eq!((overlap.earlier, overlap.later), (0, 1));
+        assert_eq!(overlap.count, 2);
+        assert_eq!(overlap.example, "a.jpg");
+        assert!(overlap.taken_by_earlier);
+        assert_eq!(report.shadowed.len(), 1);
+        assert_eq!(report.shadowed[0].rule, 1);
+        assert_eq!(report.shadowed[0].shadowed_by, [0]);
+        assert!(report.unmatched.is_empty());
+        assert!(report.has_findings());
+    }
+
+    #[test]
+    fn partial_overlaps_do_not_shadow() {
+        let samples = [
+            sample("a.jpg", &[MATCHED, MATCHED]),
+            sample("b.png", &[UNMATCHED, MATCHED]),
+        ];
+        let report = analyse(&samples, false);
+
+        assert_eq!(report.overlaps.len(), 1);
+        assert_eq!(report.overlaps[0].count, 1);
+        assert!(report.shadowed.is_empty());
+    }
+
+    #[test]
+    fn conditional_rules_only_possibly_match_names() {
+        // The earlier rule has a condition, so it might leave the files to the later one
+        let conditional = (true, None, Some(true));
+        let samples = [sample("a.jpg", &[conditional, MATCHED])];
+        let report = analyse(&samples, false);
+
+        assert_eq!(report.overlaps.len(), 1);
+        assert!(!report.overlaps[0].taken_by_earlier);
+        assert!(report.shadowed.is_empty());
+
+        // Real files are judged by the full evaluation
+        let report = analyse(&samples, true);
+        assert!(report.overlaps[0].taken_by_earlier);
+        assert_eq!(report.shadowed.len(), 1);
+        let samples = [sample("a.jpg", &[(true, None, Some(false)), MATCHED])];
+        let report = analyse(&samples, true);
+        assert!(report.overlaps.is_empty());
+        assert!(report.shadowed.is_empty());
+    }
+
+    #[test]
+    fn reports_unmatched_and_unjudged_rules() {
+        let content = (true, Some(true), None);
+        let samples = [
+            sample("a.jpg", &[MATCHED, UNMATCHED, content]),
+            sample("b.pdf", &[UNMATCHED, UNMATCHED, content]),
+        ];
+        let report = analyse(&samples, false);
+
+        assert_eq!(report.unmatched, [1]);
+        // Rules checking the content are left out of the corpus based analysis
+        assert_eq!(report.unjudged, [2]);
+        assert!(report.overlaps.is_empty());
+        assert!(report.has_findings());
+
+        // Real files are sniffed, so the content rules are judged
+        let report = analyse(&samples, true);
+        assert!(report.unjudged.is_empty());
+        assert_eq!(report.overlaps.len(), 1);
+        assert_eq!(
+            (report.overlaps[0].earlier, report.overlaps[0].later),
+            (0, 2)
+        );
+    }
+
+    #[test]
+    fn reports_nothing_without_findings_or_samples() {
+        let samples = [
+            sample("a.jpg", &[MATCHED, UNMATCHED]),
+            sample("b.pdf", &[UNMATCHED, MATCHED]),
+        ];
+        assert!(!analyse(&samples, false).has_findings());
+
+        let report = analyse(&[], true);
+        assert_eq!(report.samples, 0);
+        assert!(report.rules.is_empty());
+        assert!(!report.has_findings());
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Number of leading bytes inspected, enough for all the supported signatures
+const SNIFF_LENGTH: usize = 512;
+
+/// Detects the MIME type of a file from its leading bytes (magic numbers),
+/// independent of the file name. Returns None for unknown or unreadable files.
+pub fn sniff(path: &Path) -> Option<&'static str> {
+    let mut buffer = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)
+        .ok()?
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut buffer)
+        .ok()?;
+    detect(&buffer)
+}
+
+/// Detects the MIME type of the given leading bytes of a file
+fn detect(bytes: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, signature: &[u8]| {
+        bytes
+            .get(offset..offset + signature.len())
+            .is_some_and(|b| b == signature)
+    };
+
+    let mime = match bytes {
+        [] => return None,
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => "image/gif",
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => "image/tiff",
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => "image/webp",
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'A',
+            b'V',
+            b'E',
+            ..,
+        ] => "audio/wav",
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'A',
+            b'V',
+            b'I',
+            b' ',
+            ..,
+        ] => "video/x-msvideo",
+        [b'%', b'P', b'D', b'F', b'-', ..] => "application/pdf",
+        [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => {
+            if at(30, b"mimetypeapplication/epub+zip") {
+                "application/epub+zip"
+            } else {
+                "application/zip"
+            }
+        }
+        [0x1F, 0x8B, ..] => "application/gzip",
+        [b'B', b'Z', b'h', ..] => "application/x-bzip2",
+        [0xFD, b'7', b'z', b'X', b'Z', 0x00, ..] => "application/x-xz",
+        [b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C, ..] => "application/x-7z-compressed",
+        [b'R', b'a', b'r', b'!', 0x1A, 0x07, ..] => "application/vnd.rar",
+        [0x28, 0xB5, 0x2F, 0xFD, ..] => "application/zstd",
+        [0x7F, b'E', b'L', b'F', ..] => "application/x-executable",
+        [b'M', b'Z', ..] if !is_text(bytes) => "application/vnd.microsoft.portable-executable",
+        [0xED, 0xAB, 0xEE, 0xDB, ..] => "application/x-rpm",
+        [b'!', b'<', b'a', b'r', b'c', b'h', b'>', b'\n', ..] => {
+            if at(8, b"debian") {
+                "application/vnd.debian.binary-package"
+            } else {
+                "application/x-archive"
+            }
+        }
+        [
+            b'S',
+            b'Q',
+            b'L',
+            b'i',
+            b't',
+            b'e',
+            b' ',
+            b'f',
+            b'o',
+            b'r',
+            b'm',
+            b'a',
+            b't',
+            ..,
+        ] => "application/vnd.sqlite3",
+        [b'I', b'D', b'3', ..] => "audio/mpeg",
+        // MPEG audio frame sync, with a valid layer
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => "audio/mpeg",
+        [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+        [b'f', b'L', b'a', b'C', ..] => "audio/flac",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => match bytes.get(8..12) {
+            Some(b"qt  ") => "video/quicktime",
+            Some(b"M4A ") | Some(b"M4B ") => "audio/mp4",
+            Some(b"heic") | Some(b"heix") | Some(b"mif1") => "image/heic",
+            Some(b"avif") => "image/avif",
+            _ => "video/mp4",
+        },
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => {
+            if bytes.windows(4).any(|w| w == b"webm") {
+                "video/webm"
+            } else {
+                "video/x-matroska"
+            }
+        }
+        // Reserved header fields of a bitmap are zero
+        [b'B', b'M', _, _, _, _, 0, 0, 0, 0, ..] => "image/bmp",
+        _ if at(257, b"ustar") => "application/x-tar",
+        _ if is_text(bytes) => "text/plain",
+        _ => return None,
+    };
+
+    Some(mime)
+}
+
+/// Plain text has no NUL bytes and is valid UTF-8, a character may be cut at the end
+fn is_text(bytes: &[u8]) -> bool {
+    if bytes.contains(&0) {
+        return false;
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none() && bytes.len() == SNIFF_LENGTH,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A local file header of a ZIP archive whose first entry has the given name and content
+    fn zip(name: &str, content: &str) -> Vec<u8> {
+        let mut bytes = b"PK\x03\x04".to_vec();
+        bytes.extend([0; 14]);
+        bytes.extend((content.len() as u32).to_le_bytes());
+        bytes.extend((content.len() as u32).to_le_bytes());
+        bytes.extend((name.len() as u16).to_le_bytes());
+        bytes.extend([0; 2]);
+        bytes.extend(name.as_bytes());
+        bytes.extend(content.as_bytes());
+        bytes
+    }
+
+    /// An ISO base media file with the given major brand
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, 0x18];
+        bytes.extend(b"ftyp");
+        bytes.extend(brand);
+        bytes.extend([0, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn detects_documents_and_images() {
+        assert_eq!(detect(b"%PDF-1.7\n%\xE2\xE3"), Some("application/pdf"));
+        assert_eq!(detect(b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(detect(b"\xFF\xD8\xFF\xE0\0\x10JFIF"), Some("image/jpeg"));
+        assert_eq!(detect(b"GIF87a\x01\0"), Some("image/gif"));
+        assert_eq!(detect(b"GIF89a\x01\0"), Some("image/gif"));
+        assert_eq!(detect(b"GIF88a\x01\0"), None);
+        assert_eq!(detect(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(detect(b"RIFF\x24\0\0\0WAVEfmt "), Some("audio/wav"));
+    }
+
+    #[test]
+    fn detects_iso_media_by_brand() {
+        assert_eq!(detect(&ftyp(b"isom")), Some("video/mp4"));
+        assert_eq!(detect(&ftyp(b"mp42")), Some("video/mp4"));
+        assert_eq!(detect(&ftyp(b"qt  ")), Some("video/quicktime"));
+        assert_eq!(detect(&ftyp(b"M4A ")), Some("audio/mp4"));
+        assert_eq!(detect(&ftyp(b"heic")), Some("image/heic"));
+        assert_eq!(detect(&ftyp(b"mif1")), Some("image/heic"));
+        assert_eq!(detect(&ftyp(b"avif")), Some("image/avif"));
+    }
+
+    #[test]
+    fn tells_epub_from_other_zip_archives() {
+        assert_eq!(
+            detect(&zip("mimetype", "application/epub+zip")),
+            Some("application/epub+zip")
+        );
+        assert_eq!(
+            detect(&zip("mimetype", "application/vnd.oasis.opendocument.text")),
+            Some("application/zip")
+        );
+        // Office Open XML documents have no signature of their own
+        assert_eq!(
+            detect(&zip("[Content_Types].xml", "<?xml")),
+            Some("application/zip")
+        );
+        assert_eq!(detect(b"PK\x05\x06\0\0\0\0"), Some("application/zip"));
+    }
+
+    #[test]
+    fn tells_executables_from_text_starting_with_mz() {
+        assert_eq!(
+            detect(b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xFF\xFF"),
+            Some("application/vnd.microsoft.portable-executable")
+        );
+        assert_eq!(detect(b"MZ is a plain text note\n"), Some("text/plain"));
+        assert_eq!(
+            detect(b"\x7FELF\x02\x01\x01"),
+            Some("application/x-executable")
+        );
+    }
+
+    #[test]
+    fn detects_mpeg_audio_by_id3_or_frame_sync() {
+        assert_eq!(detect(b"ID3\x04\0\0\0\0\0\x23"), Some("audio/mpeg"));
+        // MPEG-1 layer III and MPEG-2 layer II
+        assert_eq!(detect(b"\xFF\xFB\x90\x64"), Some("audio/mpeg"));
+        assert_eq!(detect(b"\xFF\xF4\x90\x64"), Some("audio/mpeg"));
+        // The layer bits are reserved, so it is no frame
+        assert_eq!(detect(b"\xFF\xF0\x90\x64"), None);
+        // No frame sync, and not text either
+        assert_eq!(detect(b"\xFF\x1B\x90\x64"), None);
+    }
+
+    #[test]
+    fn handles_empty_and_truncated_input() {
+        assert_eq!(detect(b""), None);
+        // Too short for their signatures, so taken by what the bytes are
+        assert_eq!(detect(b"\x89PN"), None);
+        assert_eq!(detect(b"%PD"), Some("text/plain"));
+        assert_eq!(detect(b"\0\0\0\x18ftyp"), Some("video/mp4"));
+        assert_eq!(detect(b"\0\0\0\x18fty"), None);
+
+        // A character may only be cut by the end of the sniffed bytes
+        let mut text = "a".repeat(SNIFF_LENGTH - 1).into_bytes();
+        text.push(0xC3);
+        assert_eq!(detect(&text), Some("text/plain"));
+        assert_eq!(detect(&text[SNIFF_LENGTH - 4..]), None);
+        assert_eq!(detect("héllo".as_bytes()), Some("text/plain"));
+    }
+
+    #[test]
+    fn sniffs_files() {
+        let path =
+            std::env::temp_dir().join(format!("fsorg-mime-test-{}-sniff.txt", std::process::id()));
+        std::fs::write(&path, b"%PDF-1.4\n").unwrap();
+        assert_eq!(sniff(&path), Some("application/pdf"));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sniff(&path), None);
+    }
+}
@@ -1,11 +1,28 @@
 pub mod actions;
+pub mod conditions;
+pub mod config_file;
 pub mod configurations;
+pub mod conflicts;
 pub mod engine;
+pub mod glob;
+pub mod ignore;
+pub mod journal;
+pub mod lint;
+pub mod mime;
+pub mod plan;
+pub mod safeguards;
+pub mod template;
+pub mod trash;
+#[cfg(target_os = "linux")]
+pub mod watch;
 use log::error;
+use sha2::{Digest, Sha256};
 use std::env;
-use std::fs;
-use std::io::ErrorKind;
-use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 const FAIL_CONFIG_FILE: &str = "fsorg.json";
 
@@ -19,6 +36,58 @@ pub fn get_home_dir() -> Option<PathBuf> {
     .map(PathBuf::from)
 }
 
+/// Expands a leading `~` to the home directory and `$VAR` or `${VAR}` to the value of
+/// the environment variable in a path stored in the configurations
+pub fn expand_path(path: &str) -> Result<PathBuf, String> {
+    let mut expanded = String::new();
+    let mut rest = path;
+    if let Some(after) = path.strip_prefix('~')
+        && (after.is_empty() || after.starts_with(['/', '\\']))
+    {
+        let home = get_home_dir().ok_or("the home directory is unknown")?;
+        expanded.push_str(&home.to_string_lossy());
+        rest = after;
+    }
+
+    while let Some(index) = rest.find('$') {
+        expanded.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+        let (name, remaining) = match after.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed ${{ in {}", path))?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            return Err(format!("missing variable name after $ in {}", path));
+        }
+        let value =
+            env::var(name).map_err(|_| format!("undefined variable {} in {}", name, path))?;
+        expanded.push_str(&value);
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+
+    Ok(PathBuf::from(expanded))
+}
+
+/// Writes a path with the home directory as `~`, the opposite of `expand_path`
+pub fn contract_path(path: &Path) -> String {
+    match get_home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(relative) if relative.as_os_str().is_empty() => "~".to_string(),
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
 fn move_file_safely(from: &Path, to: &Path) -> std::io::Result<()> {
     if let Some(parent_dir) = to.parent() {
         fs::create_dir_all(parent_dir).inspect_err(|e| {
@@ -40,3 +109,157 @@ fn move_file_safely(from: &Path, to: &Path) -> std::io::Result<()> {
         Err(err) => Err(err),
     }
 }
+
+/// Computes the SHA-256 hash of the file content, as a hex string
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Modification time of a file, as nanoseconds since the unix epoch
+pub fn modified_nanos(metadata: &fs::Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+}
+
+/// Resolves the `.` and `..` components of a path lexically, without following links
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // Nothing is above the root
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            component => normalized.push(component.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// Replaces the content of a file atomically: the content is written to a temporary file
+/// next to it which is then renamed over it, so that the file is never left half written.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // A symbolic link is kept, its target is replaced instead
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "missing file name"))?;
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(format!(".tmp-{}", std::process::id()));
+    let temporary = path.with_file_name(temporary_name);
+
+    let result = File::create(&temporary)
+        .and_then(|mut file| {
+            if let Ok(metadata) = fs::metadata(&path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temporary, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_paths_lexically() {
+        for (path, normalized) in [
+            ("/a/b/../c", "/a/c"),
+            ("/a/./b/./c/", "/a/b/c"),
+            ("/a/b/../../..", "/"),
+            ("/../etc", "/etc"),
+            ("a/../../b", "../b"),
+            ("../../a", "../../a"),
+            ("./a/..", ""),
+            ("a//b", "a/b"),
+        ] {
+            assert_eq!(
+                normalize_path(Path::new(path)),
+                PathBuf::from(normalized),
+                "{}",
+                path
+            );
+        }
+    }
+
+    fn home() -> PathBuf {
+        get_home_dir().expect("the tests need a home directory")
+    }
+
+    #[test]
+    fn expands_the_home_directory() {
+        assert_eq!(expand_path("~"), Ok(home()));
+        assert_eq!(expand_path("~/Downloads"), Ok(home().join("Downloads")));
+        // Only a leading ~ followed by a separator is the home directory
+        assert_eq!(expand_path("~user/x"), Ok(PathBuf::from("~user/x")));
+        assert_eq!(expand_path("a/~/x"), Ok(PathBuf::from("a/~/x")));
+    }
+
+    #[test]
+    fn expands_environment_variables() {
+        let home = home().to_string_lossy().to_string();
+        assert_eq!(
+            expand_path("$HOME/Downloads"),
+            Ok(PathBuf::from(format!("{}/Downloads", home)))
+        );
+        assert_eq!(
+            expand_path("${HOME}_backup/x"),
+            Ok(PathBuf::from(format!("{}_backup/x", home)))
+        );
+        assert_eq!(
+            expand_path("/srv/$HOME-x"),
+            Ok(PathBuf::from(format!("/srv/{}-x", home)))
+        );
+        assert_eq!(
+            expand_path("$FSORG_UNDEFINED_VARIABLE/x"),
+            Err(
+                "undefined variable FSORG_UNDEFINED_VARIABLE in $FSORG_UNDEFINED_VARIABLE/x"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            expand_path("${HOME/x"),
+            Err("unclosed ${ in ${HOME/x".to_string())
+        );
+        assert_eq!(
+            expand_path("/srv/$/x"),
+            Err("missing variable name after $ in /srv/$/x".to_string())
+        );
+        assert_eq!(
+            expand_path("${}/x"),
+            Err("missing variable name after $ in ${}/x".to_string())
+        );
+    }
+
+    #[test]
+    fn contracts_paths_under_the_home_directory() {
+        let home = home();
+        assert_eq!(contract_path(&home), "~");
+        assert_eq!(contract_path(&home.join("Downloads")), "~/Downloads");
+        for path in ["~", "~/Downloads", "~/Music/Albums"] {
+            assert_eq!(contract_path(&expand_path(path).unwrap()), path);
+        }
+
+        // Only whole components of the home directory are contracted
+        let sibling = PathBuf::from(format!("{}-other/x", home.display()));
+        assert_eq!(contract_path(&sibling), sibling.display().to_string());
+        assert_eq!(contract_path(Path::new("relative/x")), "relative/x");
+    }
+}
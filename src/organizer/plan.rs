@@ -0,0 +1,358 @@
+use super::actions::{Action, ActionKind};
+use super::journal::unix_timestamp;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Identifies the structured plan files
+pub const PLAN_FORMAT: &str = "fsorg-plan";
+
+/// Latest version of the structured plan format
+pub const PLAN_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// First line of a structured plan file, every following line holds one action.
+pub struct PlanHeader {
+    pub format: String,
+    pub version: u32,
+
+    /// Working directory the plan was generated for
+    pub source_root: PathBuf,
+
+    /// Destination directory the plan was generated for
+    pub destination_root: PathBuf,
+
+    /// Hash of the configurations used for generating the plan
+    pub config_hash: String,
+
+    /// Seconds since the unix epoch when the plan was generated
+    pub created_at: u64,
+}
+
+impl PlanHeader {
+    pub fn new(source_root: PathBuf, destination_root: PathBuf, config_hash: String) -> Self {
+        PlanHeader {
+            format: PLAN_FORMAT.to_string(),
+            version: PLAN_VERSION,
+            source_root,
+            destination_root,
+            config_hash,
+            created_at: unix_timestamp(),
+        }
+    }
+}
+
+impl Default for PlanHeader {
+    fn default() -> Self {
+        PlanHeader::new(PathBuf::new(), PathBuf::new(), String::new())
+    }
+}
+
+/// Writes the plan as JSON lines: the header followed by one action per line
+pub fn write_plan(file_name: &str, header: &PlanHeader, actions: &[Action]) -> io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .open(file_name)?;
+    let mut writer = BufWriter::new(file);
+
+    serde_json::to_writer(&mut writer, header)?;
+    writer.write_all(b"\n")?;
+    for action in actions {
+        serde_json::to_writer(&mut writer, action)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()
+}
+
+/// Reads a structured plan, or a plan in the legacy `"source" -> "destination"` text format.
+/// Every malformed line is reported with its line number and fails the whole plan.
+pub fn read_plan(file_name: &str) -> io::Result<(PlanHeader, Vec<Action>)> {
+    let mut lines = vec![];
+    for (index, line) in BufReader::new(File::open(file_name)?).lines().enumerate() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push((index + 1, line));
+        }
+    }
+
+    let header = match lines.first() {
+        Some((_, line)) if line.trim_start().starts_with('{') => {
+            let (number, line) = lines.remove(0);
+            let header: PlanHeader = serde_json::from_str(&line).map_err(|err| {
+                invalid_data(format!(
+                    "{}:{}: invalid plan header: {}",
+                    file_name, number, err
+                ))
+            })?;
+            if header.format != PLAN_FORMAT {
+                return Err(invalid_data(format!(
+                    "{}: not a plan file, unknown format {}",
+                    file_name, header.format
+                )));
+            }
+            if header.version > PLAN_VERSION {
+                return Err(invalid_data(format!(
+                    "{}: plan version {} is newer than the supported version {}",
+                    file_name, header.version, PLAN_VERSION
+                )));
+            }
+            Some(header)
+        }
+        _ => None,
+    };
+
+    let mut actions = vec![];
+    let mut malformed_lines = vec![];
+    for (number, line) in &lines {
+        let action = match header {
+            Some(_) => serde_json::from_str::<Action>(line).map_err(|err| err.to_string()),
+            None => parse_legacy_line(line),
+        };
+
+        match action {
+            Ok(action) => actions.push(action),
+            Err(err) => {
+                malformed_lines.push(number.to_string());
+                error!("{}:{}: malformed plan entry: {}", file_name, number, err);
+            }
+        }
+    }
+
+    if !malformed_lines.is_empty() {
+        return Err(invalid_data(format!(
+            "{} malformed line(s) in plan {}: line {}",
+            malformed_lines.len(),
+            file_name,
+            malformed_lines.join(", ")
+        )));
+    }
+
+    Ok((header.unwrap_or_default(), actions))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parses a line of the legacy text format: "source" -> "destination" [strategy: decision]
+fn parse_legacy_line(line: &str) -> Result<Action, String> {
+    let line = line.trim();
+
+    // Quoted paths are split on the closing and opening quotes around the arrow, so that
+    // an arrow inside a file name does not split the line.
+    let (source, rest) = match line.split_once("\" -> \"") {
+        Some((source, rest)) => (source.trim_start_matches('"'), rest),
+        None => match line.split_once("->") {
+            Some((source, rest)) => (source.trim().trim_matches('"'), rest.trim()),
+            None => return Err("expected \"source\" -> \"destination\"".to_string()),
+        },
+    };
+
+    // The destination may be followed by the conflict annotation
+    let (destination, annotation) = match rest.rfind('"') {
+        Some(index) => (&rest[..index], rest[index + 1..].trim()),
+        None => match rest.rfind(" [") {
+            Some(index) if rest.ends_with(']') => (&rest[..index], rest[index..].trim()),
+            _ => (rest, ""),
+        },
+    };
+    let destination = destination.trim().trim_matches('"');
+
+    if source.is_empty() || destination.is_empty() {
+        return Err("source and destination must not be empty".to_string());
+    }
+
+    let mut action = Action {
+        kind: ActionKind::Move,
+        source: PathBuf::from(source),
+        destination: PathBuf::from(destination),
+        on_conflict: Default::default(),
+        decision: Default::default(),
+        size: None,
+        modified: None,
+        hash: None,
+        allow_absolute: false,
+        stale: false,
+    };
+
+    if !annotation.is_empty() {
+        let annotation = annotation
+            .strip_prefix('[')
+            .and_then(|a| a.strip_suffix(']'))
+            .ok_or_else(|| format!("unexpected text after the destination: {}", annotation))?;
+        let mut parts = annotation.splitn(2, ':');
+        if let Some(strategy) = parts.next() {
+            action.on_conflict = strategy.parse()?;
+        }
+        if let Some(decision) = parts.next() {
+            action.decision = decision.parse()?;
+        }
+    }
+
+    Ok(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::conflicts::{ConflictStrategy, Decision};
+    use std::path::Path;
+
+    /// Writes a plan into a temporary file which is removed once the plan is read
+    fn read_temporary_plan(name: &str, contents: &str) -> io::Result<(PlanHeader, Vec<Action>)> {
+        let path = std::env::temp_dir().join(format!(
+            "fsorg-plan-test-{}-{}.txt",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, contents)?;
+        let plan = read_plan(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        plan
+    }
+
+    #[test]
+    fn parses_quoted_legacy_lines() {
+        let action =
+            parse_legacy_line(r#""/src/my file.txt" -> "/dst/Documents/my file.txt""#).unwrap();
+        assert_eq!(action.kind, ActionKind::Move);
+        assert_eq!(action.source, Path::new("/src/my file.txt"));
+        assert_eq!(action.destination, Path::new("/dst/Documents/my file.txt"));
+        assert_eq!(action.on_conflict, ConflictStrategy::default());
+        assert_eq!(action.decision, Decision::default());
+        assert!(!action.allow_absolute);
+    }
+
+    #[test]
+    fn keeps_arrows_and_quotes_inside_names() {
+        let action = parse_legacy_line(r#""/src/a -> b.txt" -> "/dst/a -> b.txt""#).unwrap();
+        assert_eq!(action.source, Path::new("/src/a -> b.txt"));
+        assert_eq!(action.destination, Path::new("/dst/a -> b.txt"));
+
+        let action = parse_legacy_line(r#""/src/say "hi".txt" -> "/dst/say "hi".txt""#).unwrap();
+        assert_eq!(action.source, Path::new(r#"/src/say "hi".txt"#));
+        assert_eq!(action.destination, Path::new(r#"/dst/say "hi".txt"#));
+    }
+
+    #[test]
+    fn parses_unquoted_legacy_lines() {
+        let action = parse_legacy_line("  /src/a.txt -> /dst/a.txt  ").unwrap();
+        assert_eq!(action.source, Path::new("/src/a.txt"));
+        assert_eq!(action.destination, Path::new("/dst/a.txt"));
+
+        let action = parse_legacy_line("/src/a.txt -> /dst/a.txt [skip:skip]").unwrap();
+        assert_eq!(action.destination, Path::new("/dst/a.txt"));
+        assert_eq!(action.on_conflict, ConflictStrategy::Skip);
+        assert_eq!(action.decision, Decision::Skip);
+    }
+
+    #[test]
+    fn parses_conflict_annotations() {
+        let action =
+            parse_legacy_line(r#""/src/a.txt" -> "/dst/a (1).txt" [keep_newer:rename]"#).unwrap();
+        assert_eq!(action.destination, Path::new("/dst/a (1).txt"));
+        assert_eq!(action.on_conflict, ConflictStrategy::KeepNewer);
+        assert_eq!(action.decision, Decision::Rename);
+
+        let action = parse_legacy_line(r#""/src/a.txt" -> "/dst/a.txt" [overwrite]"#).unwrap();
+        assert_eq!(action.on_conflict, ConflictStrategy::Overwrite);
+        assert_eq!(action.decision, Decision::default());
+    }
+
+    #[test]
+    fn rejects_malformed_legacy_lines() {
+        for line in [
+            "garbage",
+            r#""/src/a.txt""#,
+            r#""" -> "/dst/a.txt""#,
+            r#""/src/a.txt" -> """#,
+            "->",
+            r#""/src/a.txt" -> "/dst/a.txt" trailing"#,
+            r#""/src/a.txt" -> "/dst/a.txt" [unknown:rename]"#,
+            r#""/src/a.txt" -> "/dst/a.txt" [rename:unknown]"#,
+        ] {
+            assert!(parse_legacy_line(line).is_err(), "accepted {}", line);
+        }
+    }
+
+    #[test]
+    fn skips_blank_lines_of_legacy_plans() {
+        let (header, actions) = read_temporary_plan(
+            "blank",
+            "\n\"/src/a.txt\" -> \"/dst/a.txt\"\n   \n\"/src/b.txt\" -> \"/dst/b.txt\"\n\n",
+        )
+        .unwrap();
+        assert!(header.destination_root.as_os_str().is_empty());
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[1].source, Path::new("/src/b.txt"));
+    }
+
+    #[test]
+    fn reports_the_line_numbers_of_malformed_lines() {
+        let err = read_temporary_plan(
+            "garbage",
+            "\"/src/a.txt\" -> \"/dst/a.txt\"\n\ngarbage\n\"/src/b.txt\" -> \"/dst/b.txt\"\n{not json\n",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string().starts_with("2 malformed line(s)"),
+            "{}",
+            err
+        );
+        assert!(err.to_string().ends_with(": line 3, 5"), "{}", err);
+    }
+
+    #[test]
+    fn reports_malformed_entries_of_structured_plans() {
+        let header = serde_json::to_string(&PlanHeader::new(
+            "/src".into(),
+            "/dst".into(),
+            String::new(),
+        ))
+        .unwrap();
+        let err = read_temporary_plan(
+            "structured",
+            &format!(
+                "{}\n{{\"source\":\"/src/a.txt\",\"destination\":\"/dst/a.txt\"}}\n\"/src/b.txt\" -> \"/dst/b.txt\"\n",
+                header
+            ),
+        )
+        .unwrap_err();
+        assert!(err.to_string().ends_with(": line 3"), "{}", err);
+
+        let (header, actions) = read_temporary_plan(
+            "structured-valid",
+            &format!(
+                "{}\n{{\"source\":\"/src/a.txt\",\"destination\":\"/dst/a.txt\"}}\n",
+                header
+            ),
+        )
+        .unwrap();
+        assert_eq!(header.destination_root, Path::new("/dst"));
+        assert_eq!(actions.len(), 1);
+    }
+
+    #[test]
+    fn rejects_plans_of_other_formats_and_newer_versions() {
+        let other = PlanHeader {
+            format: "other".to_string(),
+            ..Default::default()
+        };
+        let err =
+            read_temporary_plan("format", &serde_json::to_string(&other).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("unknown format other"), "{}", err);
+
+        let newer = PlanHeader {
+            version: PLAN_VERSION + 1,
+            ..Default::default()
+        };
+        let err =
+            read_temporary_plan("version", &serde_json::to_string(&newer).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("is newer than"), "{}", err);
+    }
+}
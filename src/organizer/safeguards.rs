@@ -0,0 +1,146 @@
+use super::conditions::parse_duration;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+/// Protection of files which are possibly still being written, e.g. running downloads
+pub struct SafeguardOptions {
+    /// File name suffixes of partial downloads and temporary files, matched case-insensitively
+    pub partial_suffixes: Vec<String>,
+
+    /// Files modified more recently than this are left alone, e.g. "30s", "0s" disables it
+    pub grace_period: String,
+
+    /// Leave alone the files which are currently open in any process, checked via /proc
+    pub skip_open_files: bool,
+}
+
+impl Default for SafeguardOptions {
+    fn default() -> Self {
+        SafeguardOptions {
+            partial_suffixes: [
+                ".part",
+                ".partial",
+                ".crdownload",
+                ".download",
+                ".opdownload",
+                ".!ut",
+                ".!qb",
+                ".aria2",
+                ".tmp",
+                ".temp",
+            ]
+            .iter()
+            .map(|suffix| suffix.to_string())
+            .collect(),
+            grace_period: "10s".to_string(),
+            skip_open_files: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a file is not organised yet
+pub enum Deferral {
+    /// The file name has a partial download suffix
+    Partial,
+    /// The file was modified within the grace period
+    Recent,
+    /// The file is open in some process
+    Open,
+}
+
+impl Deferral {
+    /// Whether the reason goes away by itself, a partial download gets renamed once it is complete
+    pub fn is_temporary(&self) -> bool {
+        !matches!(self, Deferral::Partial)
+    }
+}
+
+impl fmt::Display for Deferral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Deferral::Partial => "it looks like a partial download",
+            Deferral::Recent => "it was modified within the grace period",
+            Deferral::Open => "it is open in another process",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Safeguard options ready for checking files, along with a snapshot of the open files
+pub struct Safeguards {
+    partial_suffixes: Vec<String>,
+    grace_period: Duration,
+    open_files: Option<HashSet<PathBuf>>,
+}
+
+impl Safeguards {
+    pub fn new(options: &SafeguardOptions) -> Result<Self, String> {
+        Ok(Safeguards {
+            partial_suffixes: options
+                .partial_suffixes
+                .iter()
+                .map(|suffix| suffix.to_lowercase())
+                .collect(),
+            grace_period: parse_duration(&options.grace_period)
+                .map_err(|err| format!("Invalid grace period: {}", err))?,
+            open_files: options.skip_open_files.then(open_files),
+        })
+    }
+
+    /// Tells why the file must not be organised yet, if so
+    pub fn check(&self, file: &Path, metadata: Option<&Metadata>) -> Option<Deferral> {
+        let file_name = file.file_name()?.to_string_lossy().to_lowercase();
+        if self
+            .partial_suffixes
+            .iter()
+            .any(|suffix| file_name.ends_with(suffix.as_str()))
+        {
+            return Some(Deferral::Partial);
+        }
+
+        let modified = metadata.and_then(|metadata| metadata.modified().ok());
+        let age = modified.map(|m| SystemTime::now().duration_since(m).unwrap_or_default());
+        if age.is_some_and(|age| age < self.grace_period) {
+            return Some(Deferral::Recent);
+        }
+
+        if self
+            .open_files
+            .as_ref()
+            .is_some_and(|open_files| open_files.contains(file))
+        {
+            return Some(Deferral::Open);
+        }
+
+        None
+    }
+}
+
+/// Files currently open in any process, as far as the processes are visible to the user.
+/// Every process lists its open file descriptors as symbolic links in /proc/<pid>/fd.
+fn open_files() -> HashSet<PathBuf> {
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return HashSet::new();
+    };
+
+    processes
+        .flatten()
+        .filter(|process| {
+            process
+                .file_name()
+                .to_str()
+                .is_some_and(|pid| pid.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .filter_map(|process| fs::read_dir(process.path().join("fd")).ok())
+        .flat_map(|descriptors| descriptors.flatten())
+        .filter_map(|descriptor| fs::read_link(descriptor.path()).ok())
+        .filter(|target| target.is_absolute())
+        .collect()
+}
@@ -0,0 +1,305 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use regex::{Captures, Regex};
+use std::fs::Metadata;
+use std::path::Path;
+
+/// Rendered in place of metadata placeholders when the metadata is not available
+const UNKNOWN: &str = "unknown";
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    /// `{1}`: numbered capture group of the rule regex
+    Capture(usize),
+    /// `{name}`: named capture group of the rule regex
+    NamedCapture(String),
+    /// `{ext}`: file extension without the dot
+    Extension,
+    /// `{stem}`: file name without the extension
+    Stem,
+    /// `{size_bucket}`: small, medium, large or huge
+    SizeBucket,
+    /// `{mtime:%Y}`: modification time formatted with strftime syntax
+    Modified(String),
+}
+
+#[derive(Debug, Clone)]
+/// A rule destination which may contain placeholders, e.g. `Photos/{mtime:%Y}/{mtime:%m}`
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Parses a destination template, placeholders referring to capture groups are
+    /// checked against the rule regex. Literal braces are written as `{{` and `}}`.
+    pub fn parse(template: &str, regex: Option<&Regex>) -> Result<Self, String> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(format!("unclosed placeholder in {}", template)),
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Self::parse_placeholder(&placeholder, regex)?);
+                }
+                '}' => return Err(format!("unmatched }} in {}", template)),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    fn parse_placeholder(placeholder: &str, regex: Option<&Regex>) -> Result<Segment, String> {
+        let group_count = regex.map_or(0, |r| r.captures_len() - 1);
+        let has_group =
+            |name: &str| regex.is_some_and(|r| r.capture_names().flatten().any(|n| n == name));
+
+        match placeholder.split_once(':') {
+            Some(("mtime", format)) => {
+                if format.is_empty()
+                    || StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+                {
+                    return Err(format!("invalid time format in {{{}}}", placeholder));
+                }
+                Ok(Segment::Modified(format.to_string()))
+            }
+            Some(_) => Err(format!("unknown placeholder {{{}}}", placeholder)),
+            None => match placeholder {
+                "ext" => Ok(Segment::Extension),
+                "stem" => Ok(Segment::Stem),
+                "size_bucket" => Ok(Segment::SizeBucket),
+                _ => match placeholder.parse::<usize>() {
+                    Ok(group) if group >= 1 && group <= group_count => Ok(Segment::Capture(group)),
+                    Ok(group) => Err(format!(
+                        "capture group {{{}}} does not exist, the regex has {} group(s)",
+                        group, group_count
+                    )),
+                    Err(_) if has_group(placeholder) => {
+                        Ok(Segment::NamedCapture(placeholder.to_string()))
+                    }
+                    Err(_) => Err(format!("unknown placeholder {{{}}}", placeholder)),
+                },
+            },
+        }
+    }
+
+    /// Literal part of the template up to the first placeholder, cut at a path separator.
+    /// Every rendered destination lies inside of it.
+    pub fn static_prefix(&self) -> &str {
+        match self.segments.as_slice() {
+            [Segment::Literal(literal)] => literal,
+            [Segment::Literal(literal), ..] => literal.rfind('/').map_or("", |i| &literal[..i]),
+            _ => "",
+        }
+    }
+
+    /// Renders the destination for a file, `captures` are the captures of the rule regex
+    /// on the file name and `metadata` the metadata of the file, if it exists.
+    pub fn render(
+        &self,
+        captures: Option<&Captures>,
+        file_name: &str,
+        metadata: Option<&Metadata>,
+    ) -> String {
+        let path = Path::new(file_name);
+        let mut rendered = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Capture(group) => rendered.push_str(&sanitize(
+                    captures
+                        .and_then(|c| c.get(*group))
+                        .map_or("", |m| m.as_str()),
+                )),
+                Segment::NamedCapture(name) => rendered.push_str(&sanitize(
+                    captures
+                        .and_then(|c| c.name(name))
+                        .map_or("", |m| m.as_str()),
+                )),
+                Segment::Extension => rendered.push_str(
+                    &path
+                        .extension()
+                        .map_or(String::new(), |e| sanitize(&e.to_string_lossy())),
+                ),
+                Segment::Stem => rendered.push_str(
+                    &path
+                        .file_stem()
+                        .map_or(String::new(), |s| sanitize(&s.to_string_lossy())),
+                ),
+                Segment::SizeBucket => rendered
+                    .push_str(metadata.map_or(UNKNOWN, |metadata| size_bucket(metadata.len()))),
+                Segment::Modified(format) => {
+                    match metadata.and_then(|metadata| metadata.modified().ok()) {
+                        Some(modified) => rendered.push_str(
+                            &DateTime::<Local>::from(modified).format(format).to_string(),
+                        ),
+                        None => rendered.push_str(UNKNOWN),
+                    }
+                }
+            }
+        }
+
+        rendered
+    }
+}
+
+/// Values taken from the file name must not introduce new path components
+fn sanitize(value: &str) -> String {
+    match value {
+        "." | ".." => "_".to_string(),
+        _ => value.replace(['/', '\\'], "_"),
+    }
+}
+
+/// Coarse size class of a file: small (< 1 MiB), medium (< 100 MiB), large (< 1 GiB) or huge
+fn size_bucket(size: u64) -> &'static str {
+    const MIB: u64 = 1024 * 1024;
+    match size {
+        s if s < MIB => "small",
+        s if s < 100 * MIB => "medium",
+        s if s < 1024 * MIB => "large",
+        _ => "huge",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn render(template: &str, regex: &str, file_name: &str) -> String {
+        let regex = Regex::new(regex).unwrap();
+        let template = Template::parse(template, Some(&regex)).unwrap();
+        template.render(regex.captures(file_name).as_ref(), file_name, None)
+    }
+
+    #[test]
+    fn rejects_invalid_placeholders() {
+        let regex = Regex::new(r"^(\d+)-(?<artist>.+)\.mp3$").unwrap();
+        for (template, error) in [
+            ("Docs/{year}", "unknown placeholder {year}"),
+            ("Docs/{ctime:%Y}", "unknown placeholder {ctime:%Y}"),
+            (
+                "Docs/{3}",
+                "capture group {3} does not exist, the regex has 2 group(s)",
+            ),
+            (
+                "Docs/{0}",
+                "capture group {0} does not exist, the regex has 2 group(s)",
+            ),
+            ("Docs/{album}", "unknown placeholder {album}"),
+            ("Docs/{mtime:}", "invalid time format in {mtime:}"),
+            ("Docs/{mtime:%Q}", "invalid time format in {mtime:%Q}"),
+            ("Docs/{ext", "unclosed placeholder in Docs/{ext"),
+            ("Docs/ext}", "unmatched } in Docs/ext}"),
+        ] {
+            assert_eq!(
+                Template::parse(template, Some(&regex)).unwrap_err(),
+                error,
+                "{}",
+                template
+            );
+        }
+        assert!(Template::parse("Music/{1}", None).is_err());
+    }
+
+    #[test]
+    fn renders_captures_and_file_names() {
+        let regex = r"^(\d+)-(?<artist>.+)\.mp3$";
+        assert_eq!(
+            render("Music/{artist}/{1}", regex, "1999-Prince.mp3"),
+            "Music/Prince/1999"
+        );
+        assert_eq!(
+            render("{ext}/{stem}", ".*", "archive.tar.gz"),
+            "gz/archive.tar"
+        );
+        assert_eq!(render("Misc/{ext}", ".*", "Makefile"), "Misc/");
+        assert_eq!(render("{{literal}}/{ext}", ".*", "a.txt"), "{literal}/txt");
+        // An optional group which did not participate renders empty
+        assert_eq!(render("Docs/{1}x", r"^(a)?b$", "b"), "Docs/x");
+    }
+
+    #[test]
+    fn renders_metadata_placeholders() {
+        let template = Template::parse("{size_bucket}/{mtime:%Y}", None).unwrap();
+        assert_eq!(template.render(None, "a.txt", None), "unknown/unknown");
+
+        let path = std::env::temp_dir().join(format!(
+            "fsorg-template-test-{}-metadata",
+            std::process::id()
+        ));
+        let file = File::create(&path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            template.render(None, "a.txt", Some(&metadata)),
+            "small/2023"
+        );
+    }
+
+    #[test]
+    fn sanitizes_captured_values() {
+        assert_eq!(render("Docs/{1}", r"^(.*)\.pdf$", "...pdf"), "Docs/_");
+        assert_eq!(render("Docs/{stem}", ".*", "...pdf"), "Docs/_");
+        assert_eq!(sanitize("."), "_");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize("a/b\\c"), "a_b_c");
+        assert_eq!(sanitize("..."), "...");
+    }
+
+    #[test]
+    fn static_prefix_ends_before_the_first_placeholder() {
+        let prefix = |template: &str| {
+            Template::parse(template, None)
+                .unwrap()
+                .static_prefix()
+                .to_string()
+        };
+        assert_eq!(prefix("Documents/PDF"), "Documents/PDF");
+        assert_eq!(prefix("Photos/{mtime:%Y}/{mtime:%m}"), "Photos");
+        assert_eq!(prefix("Photos/Raw-{ext}"), "Photos");
+        assert_eq!(prefix("Photos{ext}"), "");
+        assert_eq!(prefix("{ext}/Photos"), "");
+        assert_eq!(prefix(""), "");
+    }
+
+    #[test]
+    fn buckets_sizes() {
+        assert_eq!(size_bucket(0), "small");
+        assert_eq!(size_bucket(1024 * 1024), "medium");
+        assert_eq!(size_bucket(100 * 1024 * 1024), "large");
+        assert_eq!(size_bucket(1024 * 1024 * 1024), "huge");
+    }
+}
@@ -0,0 +1,262 @@
+use super::conflicts::split_extension;
+use super::{get_home_dir, move_file_safely};
+use chrono::Local;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{self, Path, PathBuf};
+
+/// The home trash of the freedesktop.org trash specification:
+/// $XDG_DATA_HOME/Trash or ~/.local/share/Trash
+fn home_trash() -> io::Result<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|d| d.is_absolute())
+        .or_else(|| get_home_dir().map(|d| d.join(".local").join("share")))
+        .map(|d| d.join("Trash"))
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "unable to locate the home trash"))
+}
+
+/// A trash directory, holding the `files` and `info` directories
+struct Trash {
+    directory: PathBuf,
+
+    /// Top directory of the volume for the per-volume trashes, the original
+    /// locations are recorded relative to it
+    top_directory: Option<PathBuf>,
+}
+
+impl Trash {
+    /// Chooses the trash on the same volume as the file, so that trashing never copies
+    /// the file across devices. Falls back to the home trash.
+    fn for_file(file: &Path) -> io::Result<Self> {
+        let home_trash = home_trash()?;
+        #[cfg(unix)]
+        if let Some(trash) = volume_trash(file, &home_trash) {
+            return Ok(trash);
+        }
+
+        Ok(Trash {
+            directory: home_trash,
+            top_directory: None,
+        })
+    }
+
+    /// Original location of the file, as written to the `Path` key of the info file
+    fn original_location(&self, file: &Path) -> String {
+        let location = self
+            .top_directory
+            .as_ref()
+            .and_then(|top_directory| file.strip_prefix(top_directory).ok())
+            .unwrap_or(file);
+        encode_path(&location.to_string_lossy())
+    }
+}
+
+/// The trash of the volume holding the file, when it is not the volume of the home trash:
+/// $topdir/.Trash/$uid if the administrator provided a shared trash with the sticky bit
+/// set, $topdir/.Trash-$uid otherwise.
+#[cfg(unix)]
+fn volume_trash(file: &Path, home_trash: &Path) -> Option<Trash> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let device = file.symlink_metadata().ok()?.dev();
+    // The home trash may not exist yet, its closest existing ancestor lies on the same volume
+    let home_device = home_trash
+        .ancestors()
+        .find_map(|directory| directory.metadata().ok())?
+        .dev();
+    if device == home_device {
+        return None;
+    }
+
+    let top_directory = mount_point(file, device)?;
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+
+    let shared_trash = top_directory.join(".Trash");
+    let is_shared_trash_usable = shared_trash
+        .symlink_metadata()
+        .is_ok_and(|m| m.is_dir() && m.permissions().mode() & 0o1000 != 0);
+    if is_shared_trash_usable {
+        let directory = shared_trash.join(uid.to_string());
+        if create_private_dir(&directory).is_ok() {
+            return Some(Trash {
+                directory,
+                top_directory: Some(top_directory),
+            });
+        }
+    }
+
+    let directory = top_directory.join(format!(".Trash-{}", uid));
+    create_private_dir(&directory).ok()?;
+    Some(Trash {
+        directory,
+        top_directory: Some(top_directory),
+    })
+}
+
+/// Topmost directory above the file which still lies on the same device
+#[cfg(unix)]
+fn mount_point(file: &Path, device: u64) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut top_directory = file.parent()?;
+    while let Some(parent) = top_directory.parent() {
+        if parent.metadata().ok()?.dev() != device {
+            break;
+        }
+        top_directory = parent;
+    }
+    Some(top_directory.to_path_buf())
+}
+
+/// Creates a directory only accessible by the user, an existing one must not be a symlink
+#[cfg(unix)]
+fn create_private_dir(directory: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    match fs::DirBuilder::new().mode(0o700).create(directory) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            if directory.symlink_metadata()?.is_dir() {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not a directory", directory.display()),
+                ))
+            }
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Moves a file into the trash, along with a `.trashinfo` entry recording its original
+/// location, so that it can be restored from a file manager. Files on another volume
+/// than the home directory go into the trash of their volume.
+///
+/// Returns the path of the file inside the trash.
+pub fn trash_file(file: &Path) -> io::Result<PathBuf> {
+    let file = path::absolute(file)?;
+    let trash = Trash::for_file(&file)?;
+    let files_dir = trash.directory.join("files");
+    let info_dir = trash.directory.join("info");
+    fs::create_dir_all(&files_dir)?;
+    fs::create_dir_all(&info_dir)?;
+
+    let (name, mut info) = reserve_name(&file, &files_dir, &info_dir)?;
+    let info_file = info_dir.join(format!("{}.trashinfo", name));
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        trash.original_location(&file),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+
+    let trashed_file = files_dir.join(&name);
+    let result = info
+        .write_all(contents.as_bytes())
+        .and_then(|()| move_file_safely(&file, &trashed_file));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&info_file);
+        return Err(err);
+    }
+
+    Ok(trashed_file)
+}
+
+/// Finds a name which is free in the trash, and claims it by creating its info file.
+/// Creating the info file exclusively makes the reservation safe against concurrent trashing.
+fn reserve_name(file: &Path, files_dir: &Path, info_dir: &Path) -> io::Result<(String, fs::File)> {
+    let file_name = file
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "file has no name"))?;
+    let (stem, extension) = split_extension(&file_name);
+
+    let mut counter = 0;
+    loop {
+        let name = match counter {
+            0 => file_name.clone(),
+            _ => format!("{}.{}{}", stem, counter, extension),
+        };
+        counter += 1;
+
+        if files_dir.join(&name).symlink_metadata().is_ok() {
+            continue;
+        }
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(info_dir.join(format!("{}.trashinfo", name)))
+        {
+            Ok(info) => return Ok((name, info)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Percent-encodes a path for the `Path` key of a trash info file
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Moves a trashed file back to its original location and removes its info file
+pub fn restore(trashed_file: &Path, original: &Path) -> io::Result<()> {
+    move_file_safely(trashed_file, original)?;
+
+    if let (Some(trash), Some(name)) = (
+        trashed_file.parent().and_then(Path::parent),
+        trashed_file.file_name(),
+    ) {
+        let mut info_name = name.to_os_string();
+        info_name.push(".trashinfo");
+        let _ = fs::remove_file(trash.join("info").join(info_name));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_free_names_in_the_trash() {
+        let trash =
+            std::env::temp_dir().join(format!("fsorg-trash-test-{}-reserve", std::process::id()));
+        let _ = fs::remove_dir_all(&trash);
+        let (files_dir, info_dir) = (trash.join("files"), trash.join("info"));
+        fs::create_dir_all(&files_dir).unwrap();
+        fs::create_dir_all(&info_dir).unwrap();
+        fs::write(files_dir.join("archive.tar.gz"), "").unwrap();
+
+        let reserve = |name: &str| {
+            reserve_name(
+                Path::new("/downloads").join(name).as_path(),
+                &files_dir,
+                &info_dir,
+            )
+            .unwrap()
+            .0
+        };
+        assert_eq!(reserve("archive.tar.gz"), "archive.1.tar.gz");
+        // The info file claims the name even before the file is moved
+        assert_eq!(reserve("archive.tar.gz"), "archive.2.tar.gz");
+        assert_eq!(reserve(".bashrc"), ".bashrc");
+        assert_eq!(reserve(".bashrc"), ".bashrc.1");
+        assert!(info_dir.join("archive.2.tar.gz.trashinfo").exists());
+
+        fs::remove_dir_all(&trash).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use super::modified_nanos;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{error, warn};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often the watched files are checked for changes
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches the working directory, and its subdirectories when scanning recursively,
+/// for files which are created, written or moved into it.
+pub struct Watcher {
+    inotify: Inotify,
+
+    /// Watched directories along with their nesting level
+    directories: HashMap<WatchDescriptor, (PathBuf, usize)>,
+
+    recursive: bool,
+    max_depth: Option<usize>,
+
+    /// Directories which must not be watched, i.e. the rule destinations
+    excluded: Vec<PathBuf>,
+}
+
+impl Watcher {
+    /// Starts watching the root directory, the files already present in the watched
+    /// directories are returned along with the watcher.
+    pub fn new(
+        root: &Path,
+        recursive: bool,
+        max_depth: Option<usize>,
+        excluded: Vec<PathBuf>,
+    ) -> io::Result<(Self, Vec<PathBuf>)> {
+        let mut watcher = Watcher {
+            inotify: Inotify::init()?,
+            directories: HashMap::new(),
+            recursive,
+            max_depth,
+            excluded,
+        };
+
+        let mut files = vec![];
+        watcher.add_directory(root, 0, &mut files)?;
+        Ok((watcher, files))
+    }
+
+    /// Watches a directory and collects the files inside of it, subdirectories are
+    /// watched as well when scanning recursively.
+    fn add_directory(
+        &mut self,
+        directory: &Path,
+        depth: usize,
+        files: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        let watch_descriptor = self.inotify.watches().add(
+            directory,
+            WatchMask::CREATE | WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO,
+        )?;
+        self.directories
+            .insert(watch_descriptor, (directory.to_path_buf(), depth));
+
+        for entry in fs::read_dir(directory)?.flatten() {
+            let entry_path = entry.path();
+            // Symbolic links to directories are not followed, to avoid loops
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                self.add_subdirectory(&entry_path, depth, files);
+            } else if entry_path.is_file() {
+                files.push(entry_path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Watches a subdirectory of a directory at `depth`, if scanning descends into it
+    fn add_subdirectory(&mut self, directory: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+        let should_descend = self.recursive
+            && self.max_depth.is_none_or(|max| depth < max)
+            && !self.excluded.iter().any(|excluded| excluded == directory);
+        if !should_descend {
+            return;
+        }
+
+        if let Err(err) = self.add_directory(directory, depth + 1, files) {
+            error!("Unable to watch directory {}: {}", directory.display(), err);
+        }
+    }
+
+    /// Returns the files which appeared or were written since the last call, without blocking
+    pub fn changed_files(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut buffer = [0; 4096];
+        let mut events: Vec<(WatchDescriptor, EventMask, Option<OsString>)> = vec![];
+        loop {
+            match self.inotify.read_events(&mut buffer) {
+                Ok(batch) => events.extend(
+                    batch.map(|event| (event.wd, event.mask, event.name.map(|n| n.to_os_string()))),
+                ),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut files = vec![];
+        for (watch_descriptor, mask, name) in events {
+            if mask.contains(EventMask::Q_OVERFLOW) {
+                warn!("Missed some file system events, scanning the watched directories again");
+                self.rescan(&mut files);
+                continue;
+            }
+            if mask.contains(EventMask::IGNORED) {
+                // The watched directory was removed
+                self.directories.remove(&watch_descriptor);
+                continue;
+            }
+
+            let (Some((directory, depth)), Some(name)) =
+                (self.directories.get(&watch_descriptor).cloned(), name)
+            else {
+                continue;
+            };
+            let entry_path = directory.join(name);
+            if mask.contains(EventMask::ISDIR) {
+                // Files may have been placed inside before the watch was added
+                self.add_subdirectory(&entry_path, depth, &mut files);
+            } else {
+                files.push(entry_path);
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Collects the files of all the watched directories
+    fn rescan(&self, files: &mut Vec<PathBuf>) {
+        for (directory, _) in self.directories.values() {
+            let Ok(listings) = fs::read_dir(directory) else {
+                continue;
+            };
+            files.extend(
+                listings
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|entry_path| entry_path.is_file()),
+            );
+        }
+    }
+}
+
+/// A file seen by the watcher, waiting for its size and modification time to settle
+struct PendingFile {
+    /// Size and modification time when last checked
+    fingerprint: Option<(u64, Option<u64>)>,
+
+    /// When the fingerprint last changed
+    since: Instant,
+}
+
+/// Files which are possibly still being written
+pub struct PendingFiles {
+    files: HashMap<PathBuf, PendingFile>,
+
+    /// How long a file must stay unchanged
+    settle: Duration,
+}
+
+impl PendingFiles {
+    pub fn new(settle: Duration) -> Self {
+        PendingFiles {
+            files: HashMap::new(),
+            settle,
+        }
+    }
+
+    /// Starts tracking the files, a change restarts the settle time of a tracked file
+    pub fn track(&mut self, files: Vec<PathBuf>) {
+        for file in files {
+            self.files.insert(
+                file,
+                PendingFile {
+                    fingerprint: None,
+                    since: Instant::now(),
+                },
+            );
+        }
+    }
+
+    /// Returns the files which did not change for the settle time and stops tracking them
+    pub fn settled(&mut self) -> Vec<PathBuf> {
+        let mut settled = vec![];
+        self.files.retain(|file, pending_file| {
+            let Ok(metadata) = fs::metadata(file) else {
+                // The file is gone again
+                return false;
+            };
+            let fingerprint = Some((metadata.len(), modified_nanos(&metadata)));
+            if pending_file.fingerprint != fingerprint {
+                pending_file.fingerprint = fingerprint;
+                pending_file.since = Instant::now();
+                return true;
+            }
+            if pending_file.since.elapsed() < self.settle {
+                return true;
+            }
+
+            settled.push(file.clone());
+            false
+        });
+
+        settled
+    }
+}